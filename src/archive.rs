@@ -0,0 +1,390 @@
+use crate::error::{Result, VideobackupError};
+
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+
+// a directory gets encoded as an archive, which is a manifest followed by the contents of every file, one after another:
+// - bytes 0-7 are the length of the manifest entries
+// - then come the entries, parents always before their children, each one is
+//   - a byte for the kind, 0 for files and 1 for directories
+//   - the length of the path as 2 bytes and the path itself, relative to the archived directory with / between
+//     its components
+//   - the permissions as 4 bytes, the way unix writes them
+//   - the modification time as 8 bytes, in seconds since the unix epoch
+//   - the offset of the contents as 8 bytes, counted from the end of the manifest, and their size as 8 bytes, both
+//     are 0 for directories
+// after the manifest come the contents of the files
+
+const KIND_FILE: u8 = 0;
+const KIND_DIRECTORY: u8 = 1;
+
+/// A file or directory in an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub directory: bool,
+    pub mode: u32,
+    pub modified: u64,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// other systems only know whether something is read-only, so that's all that gets stored
+#[cfg(not(unix))]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+    if metadata.permissions().readonly() { mode & 0o555 } else { mode }
+}
+
+/// the manifest comes from the video, so setuid, setgid and sticky bits in it aren't trusted and never restored
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// adds everything in (directory) to (entries), sorted by name so the same tree always makes the same archive, and
+/// whatever is neither a file nor a directory to (skipped). (prefix) is the path of (directory) in the archive,
+/// (offset) where the contents of the next file start
+fn collect(directory: &Path, prefix: &str, entries: &mut Vec<Entry>, skipped: &mut Vec<PathBuf>, offset: &mut u64) -> Result<()> {
+    let mut children = fs::read_dir(directory)?.collect::<std::result::Result<Vec<fs::DirEntry>, io::Error>>()?;
+    children.sort_by_key(|c| c.file_name());
+    for child in children {
+        let name = match child.file_name().into_string() {
+            Ok(v) => v,
+            Err(_) => return Err(VideobackupError::InvalidParameters(format!("{} doesn't have a valid UTF-8 file name!", child.path().display()))),
+        };
+        let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        if path.len() > u16::MAX as usize {
+            return Err(VideobackupError::InvalidParameters(format!("The path {} is too long!", path)));
+        }
+        // symlink_metadata, so links don't get followed out of the directory or in circles
+        let metadata = fs::symlink_metadata(child.path())?;
+        if metadata.is_dir() {
+            entries.push(Entry { path: path.clone(), directory: true, mode: mode(&metadata), modified: modified(&metadata), offset: 0, size: 0 });
+            collect(&child.path(), &path, entries, skipped, offset)?;
+        } else if metadata.is_file() {
+            entries.push(Entry { path, directory: false, mode: mode(&metadata), modified: modified(&metadata), offset: *offset, size: metadata.len() });
+            *offset += metadata.len();
+        } else {
+            skipped.push(child.path());
+        }
+    }
+    Ok(())
+}
+
+/// the manifest entries of (entries), without the length in front of them
+fn manifest_bytes(entries: &[Entry]) -> Vec<u8> {
+    let mut manifest = Vec::new();
+    for entry in entries.iter() {
+        manifest.push(if entry.directory { KIND_DIRECTORY } else { KIND_FILE });
+        manifest.extend_from_slice(&(entry.path.len() as u16).to_be_bytes());
+        manifest.extend_from_slice(entry.path.as_bytes());
+        manifest.extend_from_slice(&entry.mode.to_be_bytes());
+        manifest.extend_from_slice(&entry.modified.to_be_bytes());
+        manifest.extend_from_slice(&entry.offset.to_be_bytes());
+        manifest.extend_from_slice(&entry.size.to_be_bytes());
+    }
+    manifest
+}
+
+/// packs everything in (directory) into the archive (output), returns its entries and the paths of everything that
+/// was left out because it's neither a file nor a directory
+pub fn write_archive(directory: &Path, output: &Path) -> Result<(Vec<Entry>, Vec<PathBuf>)> {
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    collect(directory, "", &mut entries, &mut skipped, &mut 0)?;
+    let manifest = manifest_bytes(&entries);
+
+    let mut writer = io::BufWriter::new(fs::File::create(output)?);
+    writer.write_all(&(manifest.len() as u64).to_be_bytes())?;
+    writer.write_all(&manifest)?;
+    for entry in entries.iter().filter(|e| !e.directory) {
+        let path = directory.join(&entry.path);
+        // the manifest already promised this many bytes, so the file mustn't change in the meantime
+        let copied = io::copy(&mut fs::File::open(&path)?.take(entry.size), &mut writer)?;
+        if copied != entry.size {
+            return Err(VideobackupError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} got smaller while it was archived", path.display()))));
+        }
+    }
+    writer.flush()?;
+    Ok((entries, skipped))
+}
+
+fn corrupt(reason: &str) -> VideobackupError {
+    VideobackupError::CorruptArchive(String::from(reason))
+}
+
+/// whether (path) stays inside the directory it gets extracted into
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_))) && !path.contains('\\')
+}
+
+/// reads the manifest of the archive (reader), returns its entries and where the contents of the files start
+pub fn read_manifest<R: Read>(reader: &mut R) -> Result<(Vec<Entry>, u64)> {
+    let mut length = [0; 8];
+    reader.read_exact(&mut length).map_err(|_| corrupt("the manifest is cut off"))?;
+    let length = u64::from_be_bytes(length);
+    let mut manifest = Vec::new();
+    reader.take(length).read_to_end(&mut manifest)?;
+    if (manifest.len() as u64) < length {
+        return Err(corrupt("the manifest is cut off"));
+    }
+
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut i = 0;
+    while i < manifest.len() {
+        let field = |start: usize, length: usize| manifest.get(start..(start + length)).ok_or_else(|| corrupt("the last entry is cut off"));
+        let kind = field(i, 1)?[0];
+        let path_length = u16::from_be_bytes(field(i + 1, 2)?.try_into().unwrap()) as usize;
+        let path = match String::from_utf8(field(i + 3, path_length)?.to_vec()) {
+            Ok(v) => v,
+            Err(_) => return Err(corrupt("a path isn't valid UTF-8")),
+        };
+        let rest = field(i + 3 + path_length, 28)?;
+        i += 3 + path_length + 28;
+        if kind != KIND_FILE && kind != KIND_DIRECTORY {
+            return Err(corrupt("an entry is neither a file nor a directory"));
+        }
+        if !is_safe_path(&path) {
+            return Err(VideobackupError::CorruptArchive(format!("{} would end up outside of the directory", path)));
+        }
+        entries.push(Entry {
+            path,
+            directory: kind == KIND_DIRECTORY,
+            mode: u32::from_be_bytes(rest[0..=3].try_into().unwrap()),
+            modified: u64::from_be_bytes(rest[4..=11].try_into().unwrap()),
+            offset: u64::from_be_bytes(rest[12..=19].try_into().unwrap()),
+            size: u64::from_be_bytes(rest[20..=27].try_into().unwrap()),
+        });
+    }
+    Ok((entries, 8 + length))
+}
+
+/// whether (entry) is (member) or inside of it
+fn is_in(entry: &Entry, member: &str) -> bool {
+    entry.path == member || entry.path.starts_with(&format!("{}/", member))
+}
+
+/// recreates the tree of the archive (archive) in (output), or only (member) and everything in it, keeping its path.
+/// Returns how many files and directories were extracted
+pub fn extract(archive: &Path, output: &Path, member: Option<&str>) -> Result<(u64, u64)> {
+    let mut reader = io::BufReader::new(fs::File::open(archive)?);
+    let (entries, contents_start) = read_manifest(&mut reader)?;
+    let member = member.map(|m| m.trim_matches('/'));
+    let entries: Vec<&Entry> = match member {
+        Some(m) => entries.iter().filter(|e| is_in(e, m)).collect(),
+        None => entries.iter().collect(),
+    };
+    if let Some(m) = member {
+        if entries.is_empty() {
+            return Err(VideobackupError::InvalidParameters(format!("{} isn't in the archive", m)));
+        }
+    }
+
+    fs::create_dir_all(output)?;
+    let (mut files, mut directories) = (0, 0);
+    for entry in entries.iter() {
+        let path = output.join(&entry.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if entry.directory {
+            fs::create_dir_all(&path)?;
+            directories += 1;
+            continue;
+        }
+        reader.seek(io::SeekFrom::Start(contents_start + entry.offset))?;
+        let mut file = io::BufWriter::new(fs::File::create(&path)?);
+        let copied = io::copy(&mut (&mut reader).take(entry.size), &mut file)?;
+        if copied != entry.size {
+            return Err(VideobackupError::CorruptArchive(format!("the contents of {} are cut off", entry.path)));
+        }
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(entry.modified))?;
+        set_mode(&path, entry.mode)?;
+        files += 1;
+    }
+    // directories get their permissions and times last, writing into them would change their times again and a
+    // read-only one couldn't be written into at all. Children come after their parents, so going backwards handles
+    // them first
+    for entry in entries.iter().rev().filter(|e| e.directory) {
+        let path: PathBuf = output.join(&entry.path);
+        // not every system lets us open directories to change their times
+        if let Ok(directory) = fs::File::open(&path) {
+            let _ = directory.set_modified(UNIX_EPOCH + Duration::from_secs(entry.modified));
+        }
+        set_mode(&path, entry.mode)?;
+    }
+    Ok((files, directories))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a directory of its own in the temporary directory that's removed again once the test is done
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("videobackup-rs-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TestDir(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// an archive that only has a manifest with (entries)
+    fn archive_of(entries: &[Entry]) -> Vec<u8> {
+        let manifest = manifest_bytes(entries);
+        let mut archive = (manifest.len() as u64).to_be_bytes().to_vec();
+        archive.extend_from_slice(&manifest);
+        archive
+    }
+
+    fn file(path: &str) -> Entry {
+        Entry { path: path.to_string(), directory: false, mode: 0o644, modified: 0, offset: 0, size: 0 }
+    }
+
+    #[test]
+    fn safe_paths() {
+        for path in ["a", "a/b", "dir/file.txt", "..a", "a..b/c"] {
+            assert!(is_safe_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        for path in ["", "..", "../a", "a/../b", "a/..", "/", "/etc/passwd", "./a", "..\\a", "a\\..\\b", "C:\\a"] {
+            assert!(!is_safe_path(path), "{}", path);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn prefix_paths() {
+        for path in ["C:a", "C:/a", "//server/share/a"] {
+            assert!(!is_safe_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let entries = vec![
+            Entry { path: String::from("dir"), directory: true, mode: 0o755, modified: 981173106, offset: 0, size: 0 },
+            Entry { path: String::from("dir/ü.txt"), directory: false, mode: 0o600, modified: 1, offset: 0, size: 5 },
+            Entry { path: String::from("empty"), directory: false, mode: 0o644, modified: u64::MAX, offset: 5, size: 0 },
+        ];
+        let archive = archive_of(&entries);
+        let (read, contents_start) = read_manifest(&mut archive.as_slice()).unwrap();
+        assert_eq!(read, entries);
+        assert_eq!(contents_start, archive.len() as u64);
+    }
+
+    #[test]
+    fn manifest_rejects_paths_outside() {
+        for path in ["..", "../evil", "a/../../evil", "/etc/passwd"] {
+            let archive = archive_of(&[file("a"), file(path)]);
+            assert!(matches!(read_manifest(&mut archive.as_slice()), Err(VideobackupError::CorruptArchive(_))), "{}", path);
+        }
+    }
+
+    #[test]
+    fn manifest_rejects_cut_off_entries() {
+        let archive = archive_of(&[file("a"), file("b")]);
+        assert!(matches!(read_manifest(&mut &archive[..archive.len() - 1]), Err(VideobackupError::CorruptArchive(_))));
+        // a length that ends in the middle of an entry
+        let mut archive = archive_of(&[file("a")]);
+        archive[0..8].copy_from_slice(&10u64.to_be_bytes());
+        assert!(matches!(read_manifest(&mut archive.as_slice()), Err(VideobackupError::CorruptArchive(_))));
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let test = TestDir::new("archive");
+        let source = test.0.join("source");
+        fs::create_dir_all(source.join("sub/deeper")).unwrap();
+        fs::create_dir_all(source.join("empty")).unwrap();
+        fs::write(source.join("a.bin"), [1, 2, 3]).unwrap();
+        fs::write(source.join("sub/deeper/c.txt"), "hello").unwrap();
+        fs::write(source.join("zero"), "").unwrap();
+
+        let archive = test.0.join("archive");
+        let (entries, skipped) = write_archive(&source, &archive).unwrap();
+        assert!(skipped.is_empty());
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a.bin", "empty", "sub", "sub/deeper", "sub/deeper/c.txt", "zero"]);
+        let (read, _) = read_manifest(&mut fs::File::open(&archive).unwrap()).unwrap();
+        assert_eq!(read, entries);
+
+        let output = test.0.join("output");
+        assert_eq!(extract(&archive, &output, None).unwrap(), (3, 3));
+        assert_eq!(fs::read(output.join("a.bin")).unwrap(), [1, 2, 3]);
+        assert_eq!(fs::read_to_string(output.join("sub/deeper/c.txt")).unwrap(), "hello");
+        assert_eq!(fs::read(output.join("zero")).unwrap(), []);
+        assert!(output.join("empty").is_dir());
+        assert_eq!(mode(&fs::metadata(output.join("a.bin")).unwrap()), mode(&fs::metadata(source.join("a.bin")).unwrap()));
+
+        let member = test.0.join("member");
+        assert_eq!(extract(&archive, &member, Some("sub/")).unwrap(), (1, 2));
+        assert!(member.join("sub/deeper/c.txt").is_file());
+        assert!(!member.join("a.bin").exists());
+        assert!(matches!(extract(&archive, &member, Some("missing")), Err(VideobackupError::InvalidParameters(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_bits_are_not_extracted() {
+        let test = TestDir::new("setuid");
+        let archive = test.0.join("archive");
+        fs::write(&archive, archive_of(&[Entry { mode: 0o4755, ..file("a") }, Entry { directory: true, mode: 0o3755, ..file("dir") }])).unwrap();
+        let output = test.0.join("output");
+        assert_eq!(extract(&archive, &output, None).unwrap(), (1, 1));
+        assert_eq!(mode(&fs::metadata(output.join("a")).unwrap()), 0o755);
+        assert_eq!(mode(&fs::metadata(output.join("dir")).unwrap()), 0o755);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_skipped() {
+        let test = TestDir::new("symlink");
+        let source = test.0.join("source");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a"), "a").unwrap();
+        std::os::unix::fs::symlink("a", source.join("link")).unwrap();
+        let (entries, skipped) = write_archive(&source, &test.0.join("archive")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(skipped, [source.join("link")]);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::common::FrameLayout;
+use crate::palette::Palette;
+
+
+/// the calibration frame is made of swatches of (SWATCH_CELLS)x(SWATCH_CELLS) cells, each in a single color of the palette
+pub const SWATCH_CELLS: usize = 4;
+
+/// the palette gets learned separately for each of (REGIONS)x(REGIONS) regions of the frame, since some players and
+/// codecs don't shift the colors the same way everywhere
+const REGIONS: usize = 4;
+
+/// how many cells of a frame k-means looks at
+const CLUSTER_SAMPLES: usize = 4096;
+
+/// k-means gives up after this many rounds if the centroids still move
+const CLUSTER_ROUNDS: usize = 16;
+
+/// the symbol of the swatch in swatch column (x) and swatch row (y), the diagonal stripes make sure every color
+/// shows up all over the frame
+fn swatch_symbol(x: usize, y: usize, colors: u16) -> usize {
+    (x + y) % colors as usize
+}
+
+/// the squared distance between two colors
+fn distance(a: &[u8; 3], b: &[u8; 3]) -> i32 {
+    (0..3).map(|c| (a[c] as i32 - b[c] as i32) * (a[c] as i32 - b[c] as i32)).sum()
+}
+
+/// the average color of a sum of the red, green and blue values of (sum[3]) colors
+fn average(sum: &[u64; 4]) -> Option<[u8; 3]> {
+    if sum[3] == 0 {
+        return None;
+    }
+    Some([(sum[0] / sum[3]) as u8, (sum[1] / sum[3]) as u8, (sum[2] / sum[3]) as u8])
+}
+
+/// the bytes that make build_frame draw the calibration frame for (layout)
+pub fn calibration_bytes(layout: FrameLayout) -> Vec<u8> {
+    let bits = layout.colors.trailing_zeros();
+    let columns = layout.columns();
+    let mut bytes: Vec<u8> = Vec::with_capacity(layout.bytes());
+    let mut pending: u32 = 0;
+    let mut pending_bits: u32 = 0;
+    for cell in 0..layout.cells() {
+        let symbol = swatch_symbol((cell % columns) / SWATCH_CELLS, (cell / columns) / SWATCH_CELLS, layout.colors);
+        pending = (pending << bits) | symbol as u32;
+        pending_bits += bits;
+        while pending_bits >= 8 {
+            pending_bits -= 8;
+            bytes.push((pending >> pending_bits) as u8);
+        }
+        pending &= (1 << pending_bits) - 1;
+    }
+    // cells that don't fill up a whole byte stay black
+    bytes
+}
+
+/// The palette as it actually arrived, learned from the calibration frame or by clustering the colors of a frame.
+pub struct Calibration {
+    columns: usize,
+    rows: usize,
+    /// the frame is split into (regions)x(regions) regions
+    regions: usize,
+    /// the learned palette of every region, row by row
+    palettes: Vec<Vec<[u8; 3]>>,
+}
+
+impl Calibration {
+    /// learns the palette from a calibration frame, (read_cell) reads the average color of a cell. Returns None if the
+    /// colors are so close to each other that this probably isn't a calibration frame at all
+    pub fn learn<F: Fn(usize) -> [u8; 3]>(layout: FrameLayout, read_cell: F) -> Option<Calibration> {
+        let palette = Palette::new(layout.colors, layout.palette);
+        let colors = layout.colors as usize;
+        let (columns, rows) = (layout.columns(), layout.rows());
+        // only the cells build_frame actually drew count
+        let drawn_cells = layout.bytes() * 8 / palette.bits() as usize;
+
+        // sums of the red, green and blue values and the amount of samples for every symbol in every region,
+        // the last "region" is the whole frame
+        let mut sums: Vec<Vec<[u64; 4]>> = vec![vec![[0; 4]; colors]; REGIONS * REGIONS + 1];
+        for swatch_y in 0..(rows / SWATCH_CELLS) {
+            for swatch_x in 0..(columns / SWATCH_CELLS) {
+                let symbol = swatch_symbol(swatch_x, swatch_y, layout.colors);
+                let (x, y) = (swatch_x * SWATCH_CELLS, swatch_y * SWATCH_CELLS);
+                let region = ((y + SWATCH_CELLS / 2) * REGIONS / rows).min(REGIONS - 1) * REGIONS
+                           + ((x + SWATCH_CELLS / 2) * REGIONS / columns).min(REGIONS - 1);
+                // the outer cells of a swatch get smeared into its neighbours
+                for cell_y in (y + 1)..(y + SWATCH_CELLS - 1) {
+                    for cell_x in (x + 1)..(x + SWATCH_CELLS - 1) {
+                        let cell = cell_y * columns + cell_x;
+                        if cell >= drawn_cells {
+                            continue;
+                        }
+                        let color = read_cell(cell);
+                        for r in [region, REGIONS * REGIONS] {
+                            for c in 0..3 {
+                                sums[r][symbol][c] += color[c] as u64;
+                            }
+                            sums[r][symbol][3] += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        // regions without a swatch of some color use the average over the whole frame, or the ideal color if there's none
+        let whole_frame: Vec<[u8; 3]> = (0..colors).map(|s| average(&sums[REGIONS * REGIONS][s]).unwrap_or(palette.color(s))).collect();
+        let palettes: Vec<Vec<[u8; 3]>> = (0..REGIONS * REGIONS)
+                .map(|r| (0..colors).map(|s| average(&sums[r][s]).unwrap_or(whole_frame[s])).collect())
+                .collect();
+
+        // the learned colors may drift, but they can't end up much closer to each other than the ideal ones are
+        let closest = |colors: &[[u8; 3]]| -> i32 {
+            let mut closest = i32::MAX;
+            for (i, a) in colors.iter().enumerate() {
+                for b in colors[(i + 1)..].iter() {
+                    closest = closest.min(distance(a, b));
+                }
+            }
+            closest
+        };
+        if closest(&whole_frame) * 16 < closest(palette.colors()) {
+            return None;
+        }
+
+        Some(Calibration { columns, rows, regions: REGIONS, palettes })
+    }
+
+    /// learns the palette of a data frame without any calibration frame by running k-means over some of its cells,
+    /// (read_cell) reads the average color of a cell. The centroids start out as the ideal colors, so every centroid
+    /// stays with the symbol of the color it started out as
+    pub fn cluster<F: Fn(usize) -> [u8; 3]>(layout: FrameLayout, read_cell: F) -> Calibration {
+        let palette = Palette::new(layout.colors, layout.palette);
+        let step = layout.cells().div_ceil(CLUSTER_SAMPLES).max(1);
+        let samples: Vec<[u8; 3]> = (0..layout.cells()).step_by(step).map(read_cell).collect();
+
+        let mut centroids: Vec<[u8; 3]> = palette.colors().to_vec();
+        for _ in 0..CLUSTER_ROUNDS {
+            let mut sums: Vec<[u64; 4]> = vec![[0; 4]; centroids.len()];
+            for sample in samples.iter() {
+                let nearest = (0..centroids.len()).min_by_key(|&i| distance(sample, &centroids[i])).unwrap();
+                for c in 0..3 {
+                    sums[nearest][c] += sample[c] as u64;
+                }
+                sums[nearest][3] += 1;
+            }
+            // colors that don't show up in the frame keep their centroid
+            let moved: Vec<[u8; 3]> = sums.iter().zip(centroids.iter())
+                    .map(|(sum, &centroid)| average(sum).unwrap_or(centroid))
+                    .collect();
+            if moved == centroids {
+                break;
+            }
+            centroids = moved;
+        }
+
+        Calibration { columns: layout.columns(), rows: layout.rows(), regions: 1, palettes: vec![centroids] }
+    }
+
+    fn region(&self, column: usize, row: usize) -> usize {
+        let x = (column * self.regions / self.columns).min(self.regions - 1);
+        let y = (row * self.regions / self.rows).min(self.regions - 1);
+        y * self.regions + x
+    }
+
+    /// the learned palette for the region (cell) is in
+    pub fn palette(&self, cell: usize) -> &[[u8; 3]] {
+        &self.palettes[self.region(cell % self.columns, cell / self.columns)]
+    }
+
+    /// how far the learned colors are from the ideal ones on average over the whole frame, per color channel
+    pub fn drift(&self, ideal: &Palette) -> f32 {
+        let mut sum: u64 = 0;
+        let mut count: u64 = 0;
+        for palette in self.palettes.iter() {
+            for (learned, ideal) in palette.iter().zip(ideal.colors()) {
+                for c in 0..3 {
+                    sum += (learned[c] as i32 - ideal[c] as i32).unsigned_abs() as u64;
+                    count += 1;
+                }
+            }
+        }
+        sum as f32 / count as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::build_frame;
+    use crate::palette::PaletteFamily;
+
+    use std::convert::TryInto;
+
+    const LAYOUT: FrameLayout = FrameLayout { width: 128, height: 128, colors: 8, palette: PaletteFamily::Color, pixel_size: 1,
+                                              interleaved: false, fiducials: false };
+
+    /// how a player might shift a color, with less contrast and a tint that's different on the left and right half
+    fn shifted(color: &[u8; 3], right: bool) -> [u8; 3] {
+        let tint: [i32; 3] = if right { [-10, 5, 15] } else { [12, -6, 0] };
+        let mut shifted = [0; 3];
+        for c in 0..3 {
+            shifted[c] = (color[c] as i32 * 3 / 4 + 32 + tint[c]).clamp(0, 255) as u8;
+        }
+        shifted
+    }
+
+    /// the shifted color of cell (cell) of (frame) as it was drawn by build_frame
+    fn read_cell(frame: &[u8], cell: usize) -> [u8; 3] {
+        let (x, y) = LAYOUT.cell_position(cell);
+        let pixel = (y * LAYOUT.width + x) * 3;
+        shifted(&frame[pixel..(pixel + 3)].try_into().unwrap(), x >= LAYOUT.width / 2)
+    }
+
+    #[test]
+    fn palette_is_learned() {
+        let frame = build_frame(&calibration_bytes(LAYOUT), LAYOUT).unwrap();
+        let calibration = Calibration::learn(LAYOUT, |c| read_cell(&frame, c)).unwrap();
+        let palette = Palette::new(LAYOUT.colors, LAYOUT.palette);
+        // every region learns the colors as they look there
+        for (cell, right) in [(0, false), (LAYOUT.columns() - 1, true), (LAYOUT.cells() - LAYOUT.columns(), false), (LAYOUT.cells() - 1, true)] {
+            let expected: Vec<[u8; 3]> = palette.colors().iter().map(|c| shifted(c, right)).collect();
+            assert_eq!(calibration.palette(cell), expected.as_slice(), "{}", cell);
+        }
+        let drift = calibration.drift(&palette);
+        assert!(drift > 10.0 && drift < 40.0, "{}", drift);
+    }
+
+    #[test]
+    fn data_frames_are_not_learned_from() {
+        let bytes: Vec<u8> = (0..LAYOUT.bytes()).map(|i| (i * 151 + 7) as u8).collect();
+        let frame = build_frame(&bytes, LAYOUT).unwrap();
+        assert!(Calibration::learn(LAYOUT, |c| read_cell(&frame, c)).is_none());
+        assert!(Calibration::learn(LAYOUT, |_| [0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn data_frames_are_clustered() {
+        let palette = Palette::new(LAYOUT.colors, LAYOUT.palette);
+        let bytes: Vec<u8> = (0..LAYOUT.bytes()).map(|i| (i * 151 + 7) as u8).collect();
+        let frame = build_frame(&bytes, LAYOUT).unwrap();
+        let calibration = Calibration::cluster(LAYOUT, |c| {
+            let (x, y) = LAYOUT.cell_position(c);
+            let pixel = (y * LAYOUT.width + x) * 3;
+            shifted(&frame[pixel..(pixel + 3)].try_into().unwrap(), false)
+        });
+        let expected: Vec<[u8; 3]> = palette.colors().iter().map(|c| shifted(c, false)).collect();
+        assert_eq!(calibration.palette(0), expected.as_slice());
+        assert_eq!(calibration.palette(LAYOUT.cells() - 1), expected.as_slice());
+
+        // colors that aren't in the frame stay where they are
+        let calibration = Calibration::cluster(LAYOUT, |_| shifted(&palette.color(0), false));
+        assert_eq!(calibration.palette(0)[0], shifted(&palette.color(0), false));
+        assert_eq!(calibration.palette(0)[1..], palette.colors()[1..]);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::error::{Result, VideobackupError};
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+
+/// The video codecs the encoder knows how to drive, each with its own set of ffmpeg arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoCodec {
+    X264,
+    X265,
+    Vp9,
+    Av1,
+    /// lossless, so the CRF gets ignored, but the files get huge
+    Ffv1,
+}
+
+impl VideoCodec {
+    /// the name ffmpeg knows the encoder by
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            VideoCodec::X264 => "libx264",
+            VideoCodec::X265 => "libx265",
+            VideoCodec::Vp9 => "libvpx-vp9",
+            VideoCodec::Av1 => "libaom-av1",
+            VideoCodec::Ffv1 => "ffv1",
+        }
+    }
+
+    /// the name ffmpeg knows (pixel_format) by for this codec
+    pub fn pixel_format(&self, pixel_format: PixelFormat) -> &'static str {
+        match (self, pixel_format) {
+            // FFV1 stays lossless by storing RGB as it is
+            (VideoCodec::Ffv1, PixelFormat::Yuv444p) => "gbrp",
+            (_, PixelFormat::Yuv444p) => "yuv444p",
+            (_, PixelFormat::Yuv420p) => "yuv420p",
+        }
+    }
+
+    /// the range of CRF values the encoder accepts, None for lossless codecs
+    pub fn crf_range(&self) -> Option<(u16, u16)> {
+        match self {
+            VideoCodec::X264 | VideoCodec::X265 => Some((0, 51)),
+            VideoCodec::Vp9 | VideoCodec::Av1 => Some((0, 63)),
+            VideoCodec::Ffv1 => None,
+        }
+    }
+
+    /// the containers (by file extension) that can hold this codec
+    pub fn containers(&self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::X264 => &["mp4", "mkv", "mov", "ts", "flv"],
+            VideoCodec::X265 => &["mp4", "mkv", "mov", "ts"],
+            VideoCodec::Vp9 => &["webm", "mkv", "mp4"],
+            VideoCodec::Av1 => &["mp4", "mkv", "webm"],
+            VideoCodec::Ffv1 => &["mkv", "avi", "nut"],
+        }
+    }
+
+    /// arguments that tell the encoder which quality to aim for
+    fn quality_args(&self, crf: u16) -> Vec<String> {
+        let crf = crf.to_string();
+        let args: Vec<&str> = match self {
+            VideoCodec::X264 | VideoCodec::X265 => vec!["-crf", &crf, "-preset", "medium"],
+            // the libvpx and libaom encoders only do constant quality when the bitrate is set to 0
+            VideoCodec::Vp9 => vec!["-crf", &crf, "-b:v", "0", "-row-mt", "1"],
+            VideoCodec::Av1 => vec!["-crf", &crf, "-b:v", "0", "-cpu-used", "6", "-row-mt", "1"],
+            VideoCodec::Ffv1 => vec!["-level", "3", "-g", "1"],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// bitstream filters and tags the container needs for this codec
+    fn container_args(&self, container: &str) -> Vec<String> {
+        let args: &[&str] = match (self, container) {
+            (VideoCodec::X264, "ts") => &["-bsf:v", "h264_mp4toannexb"],
+            (VideoCodec::X265, "ts") => &["-bsf:v", "hevc_mp4toannexb"],
+            // players from Apple refuse HEVC in MP4/MOV unless it's tagged as hvc1
+            (VideoCodec::X265, "mp4") | (VideoCodec::X265, "mov") => &["-tag:v", "hvc1"],
+            _ => &[],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// checks whether this codec, (output)'s container and (crf) can be used together
+    pub fn validate(&self, output: &Path, crf: u16) -> Result<()> {
+        let container = container_of(output);
+        if !self.containers().contains(&container.as_str()) {
+            return Err(VideobackupError::InvalidParameters(format!("{} can't be stored in a .{} file, use one of these containers: {}",
+                                                                   self, container, self.containers().join(", "))));
+        }
+        if let Some((min, max)) = self.crf_range() {
+            if crf < min || crf > max {
+                return Err(VideobackupError::InvalidParameters(format!("{} only accepts CRF values from {} to {}", self, min, max)));
+            }
+        }
+        Ok(())
+    }
+
+    /// all the output arguments ffmpeg needs for encoding (output) with this codec
+    pub fn ffmpeg_args(&self, output: &Path, crf: u16, pixel_format: PixelFormat) -> Vec<String> {
+        let mut args = vec![String::from("-c:v"), String::from(self.encoder()), String::from("-pix_fmt"), String::from(self.pixel_format(pixel_format))];
+        args.extend(self.quality_args(crf));
+        args.extend(self.container_args(&container_of(output)));
+        args
+    }
+}
+
+/// the container of (output) as told by its extension
+fn container_of(output: &Path) -> String {
+    output.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            VideoCodec::X264 => "x264",
+            VideoCodec::X265 => "x265",
+            VideoCodec::Vp9 => "vp9",
+            VideoCodec::Av1 => "av1",
+            VideoCodec::Ffv1 => "ffv1",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for VideoCodec {
+    type Err = VideobackupError;
+
+    /// accepts our own names as well as the names of the ffmpeg encoders
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "x264" | "h264" | "libx264" => Ok(VideoCodec::X264),
+            "x265" | "h265" | "hevc" | "libx265" => Ok(VideoCodec::X265),
+            "vp9" | "libvpx-vp9" => Ok(VideoCodec::Vp9),
+            "av1" | "libaom-av1" => Ok(VideoCodec::Av1),
+            "ffv1" => Ok(VideoCodec::Ffv1),
+            _ => Err(VideobackupError::InvalidParameters(format!("{} is not a supported video codec", s))),
+        }
+    }
+}
+
+/// How the colors of the frames get stored. Full resolution colors keep color palettes intact, but x264 and x265 can
+/// only store them with their High 4:4:4 profiles, which a lot of players and upload platforms reject or re-encode
+/// badly. Those platforms store the colors at a quarter of the resolution anyway, so for them it's better to do that
+/// right away and use a grayscale palette, which survives it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// colors at full resolution, FFV1 stores RGB instead
+    Yuv444p,
+    /// colors at a quarter of the resolution (4:2:0 chroma subsampling), what every player and platform can handle
+    Yuv420p,
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PixelFormat::Yuv444p => "yuv444p",
+            PixelFormat::Yuv420p => "yuv420p",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = VideobackupError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "yuv444p" | "444" => Ok(PixelFormat::Yuv444p),
+            "yuv420p" | "420" => Ok(PixelFormat::Yuv420p),
+            _ => Err(VideobackupError::InvalidParameters(format!("{} is not a supported pixel format", s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn containers_are_checked() {
+        for (codec, output) in [(VideoCodec::X264, "a.mp4"), (VideoCodec::X265, "a.MKV"), (VideoCodec::Vp9, "a.webm"), (VideoCodec::Av1, "a.mp4"),
+                                (VideoCodec::Ffv1, "a.mkv")] {
+            assert!(codec.validate(Path::new(output), 20).is_ok(), "{} {}", codec, output);
+        }
+        for (codec, output) in [(VideoCodec::X264, "a.webm"), (VideoCodec::X265, "a.flv"), (VideoCodec::Vp9, "a.ts"), (VideoCodec::Ffv1, "a.mp4"),
+                                (VideoCodec::X264, "a")] {
+            assert!(matches!(codec.validate(Path::new(output), 20), Err(VideobackupError::InvalidParameters(_))), "{} {}", codec, output);
+        }
+    }
+
+    #[test]
+    fn crf_is_checked() {
+        assert!(VideoCodec::X264.validate(Path::new("a.mp4"), 0).is_ok());
+        assert!(VideoCodec::X264.validate(Path::new("a.mp4"), 51).is_ok());
+        assert!(matches!(VideoCodec::X264.validate(Path::new("a.mp4"), 52), Err(VideobackupError::InvalidParameters(_))));
+        assert!(VideoCodec::Vp9.validate(Path::new("a.webm"), 63).is_ok());
+        assert!(matches!(VideoCodec::Av1.validate(Path::new("a.webm"), 64), Err(VideobackupError::InvalidParameters(_))));
+        // lossless, so any CRF goes
+        assert!(VideoCodec::Ffv1.validate(Path::new("a.mkv"), 1000).is_ok());
+    }
+}
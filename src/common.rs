@@ -1,38 +1,218 @@
 extern crate crc32fast;
+extern crate getrandom;
+
+use crate::error::Result;
+use crate::geometry::FIDUCIAL_CELLS;
+use crate::palette::PaletteFamily;
 
 use crc32fast::Hasher;
 
 use std::fs;
+use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
 
-pub static ENCODING_VERSION: u16 = 3;
+pub static ENCODING_VERSION: u16 = 4;
 
 pub static BLOCK_SIZE: u8 = 128;
 
-pub fn zero_vec(size: usize) -> Vec<u8> {
-    let mut vec: Vec<u8> = Vec::with_capacity(size);
-    for i in 0..size {
-        vec.push(0);
+/// how many copies of the metadata a metadata frame holds at most, an odd amount so voting on them never ties
+pub const METADATA_COPIES: usize = 5;
+
+/// the largest cells we draw, in pixels per side
+pub static MAX_PIXEL_SIZE: u8 = 16;
+
+/// how the data is drawn into a frame: every (pixel_size)x(pixel_size) cell holds one color out of the (colors) colors of
+/// a (palette) palette, the cells are filled row by row and the pixels that don't make up a full cell at the right and
+/// bottom edge stay black. With (fiducials), the outer FIDUCIAL_CELLS rings of cells hold the fiducials instead of data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameLayout {
+    pub width: usize,
+    pub height: usize,
+    pub colors: u16,
+    pub palette: PaletteFamily,
+    pub pixel_size: u8,
+    /// whether the bytes of the blocks in a data frame are interleaved
+    pub interleaved: bool,
+    /// whether the frame has fiducials around its cells
+    pub fiducials: bool,
+}
+
+impl FrameLayout {
+    /// how many rings of cells on every side don't hold data
+    fn margin(&self) -> usize {
+        if self.fiducials { FIDUCIAL_CELLS } else { 0 }
+    }
+
+    /// the amount of columns of cells in the whole frame, including the fiducials
+    pub fn grid_columns(&self) -> usize {
+        self.width / self.pixel_size.max(1) as usize
+    }
+
+    pub fn grid_rows(&self) -> usize {
+        self.height / self.pixel_size.max(1) as usize
+    }
+
+    /// the amount of columns of cells that hold data
+    pub fn columns(&self) -> usize {
+        self.grid_columns().saturating_sub(2 * self.margin())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.grid_rows().saturating_sub(2 * self.margin())
+    }
+
+    /// the position of the top left pixel of the cell with the given number
+    pub fn cell_position(&self, cell: usize) -> (usize, usize) {
+        let pixel_size = self.pixel_size as usize;
+        ((self.margin() + cell % self.columns()) * pixel_size, (self.margin() + cell / self.columns()) * pixel_size)
+    }
+
+    pub fn cells(&self) -> usize {
+        self.columns() * self.rows()
+    }
+
+    /// how many bytes fit into a frame
+    pub fn bytes(&self) -> usize {
+        self.cells() * self.colors.trailing_zeros() as usize / 8
+    }
+
+    /// how many blocks fit into a frame after (reserved_bytes) bytes at its start
+    pub fn blocks_per_frame(&self, reserved_bytes: usize) -> usize {
+        self.bytes().saturating_sub(reserved_bytes) / BLOCK_SIZE as usize
+    }
+
+    /// the layout of the metadata frame, it always has the 2 color palette
+    pub fn metadata(self) -> Self {
+        FrameLayout { colors: 2, palette: PaletteFamily::Color, interleaved: false, ..self }
+    }
+}
+
+/// where the copies of the (size) bytes of metadata start in a metadata frame that holds (frame_bytes) bytes, they're spread evenly over
+/// the frame so damage to one spot only hits one of them. The first copy is always at the very start, where videos from
+/// before the copies existed have their only one
+pub fn metadata_offsets(frame_bytes: usize, size: usize) -> Vec<usize> {
+    let copies = (frame_bytes / size.max(1)).clamp(1, METADATA_COPIES);
+    let stride = frame_bytes / copies;
+    (0..copies).map(|i| i * stride).collect()
+}
+
+/// spreads the bytes of (blocks) consecutive blocks across all of them: the first bytes of every block come first, then
+/// the second bytes and so on, so damage to a small area of the frame hits a few bytes of a lot of blocks instead of
+/// a lot of bytes of a few blocks
+pub fn interleave<T: Copy>(data: &[T], blocks: usize) -> Vec<T> {
+    let block_size = BLOCK_SIZE as usize;
+    let mut interleaved = Vec::with_capacity(blocks * block_size);
+    for j in 0..block_size {
+        for b in 0..blocks {
+            interleaved.push(data[b * block_size + j]);
+        }
     }
-    return vec;
+    interleaved
 }
 
-pub fn crc32_file(filename: &str) -> u32 {
-    let mut file = fs::File::open(filename).unwrap();
+/// undoes interleave
+pub fn deinterleave<T: Copy>(data: &[T], blocks: usize) -> Vec<T> {
+    let block_size = BLOCK_SIZE as usize;
+    let mut deinterleaved = Vec::with_capacity(blocks * block_size);
+    for b in 0..blocks {
+        for j in 0..block_size {
+            deinterleaved.push(data[j * blocks + b]);
+        }
+    }
+    deinterleaved
+}
+
+/// fills (buf) with cryptographically secure random bytes from the operating system
+pub fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    match getrandom::getrandom(buf) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(io::Error::other(e.to_string()).into()),
+    }
+}
+
+/// a file that gets deleted again when it goes out of scope, even if something went wrong on the way
+pub struct TempFile {
+    pub path: PathBuf,
+}
+
+impl TempFile {
+    /// a hidden file next to (path) whose name ends with (suffix)
+    pub fn next_to(path: &Path, suffix: &str) -> Self {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        TempFile { path: path.with_file_name(format!(".{}.{}", name, suffix)) }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub fn zero_vec(size: usize) -> Vec<u8> {
+    vec![0; size]
+}
+
+pub fn crc32_file<P: AsRef<Path>>(filename: P) -> Result<u32> {
+    let mut file = fs::File::open(filename)?;
     let mut hasher = Hasher::new();
 
     // read the file in 1MiB pieces
     const BUF_SIZE: usize = 1024*1024;
     let mut buf: [u8; BUF_SIZE] = [0; BUF_SIZE];
-    while let Ok(n) = file.read(&mut buf[..]) {
-        if n != BUF_SIZE {
-            let rest = &buf[0..n];
-            hasher.update(rest);
+    loop {
+        let n = file.read(&mut buf[..])?;
+        if n == 0 {
             break;
-        } else {
-            hasher.update(&buf);
+        }
+        hasher.update(&buf[0..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleave_round_trip() {
+        for blocks in [1, 2, 7] {
+            let data: Vec<u16> = (0..(blocks * BLOCK_SIZE as usize) as u16).collect();
+            let interleaved = interleave(&data, blocks);
+            assert_eq!(interleaved.len(), data.len());
+            assert_eq!(deinterleave(&interleaved, blocks), data);
         }
     }
-    return hasher.finalize();
-}
\ No newline at end of file
+
+    #[test]
+    fn interleave_spreads_blocks() {
+        let blocks = 3;
+        let data: Vec<usize> = (0..blocks * BLOCK_SIZE as usize).collect();
+        let interleaved = interleave(&data, blocks);
+        // the first bytes of every block come first
+        assert_eq!(interleaved[0..6], [0, BLOCK_SIZE as usize, 2 * BLOCK_SIZE as usize, 1, BLOCK_SIZE as usize + 1, 2 * BLOCK_SIZE as usize + 1]);
+        // a run of damaged bytes only hits every block a few times
+        for run in interleaved.chunks(blocks) {
+            let mut hit: Vec<usize> = run.iter().map(|i| i / BLOCK_SIZE as usize).collect();
+            hit.sort_unstable();
+            assert_eq!(hit, [0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn metadata_copies_dont_overlap() {
+        for (frame_bytes, size) in [(250, 250), (1000, 250), (1249, 250), (100000, 750), (5000, 2000), (100000, 2000)] {
+            let offsets = metadata_offsets(frame_bytes, size);
+            assert_eq!(offsets[0], 0);
+            assert_eq!(offsets.len(), (frame_bytes / size).clamp(1, METADATA_COPIES));
+            // every copy fits into the frame and ends before the next one starts
+            for (i, offset) in offsets.iter().enumerate() {
+                assert!(offset + size <= offsets.get(i + 1).copied().unwrap_or(frame_bytes), "{} {}", frame_bytes, size);
+            }
+        }
+        // a frame that's too small for a whole copy still gets one, cut off at its end
+        assert_eq!(metadata_offsets(200, 250), [0]);
+    }
+}
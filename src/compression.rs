@@ -0,0 +1,121 @@
+extern crate xz2;
+extern crate zstd;
+
+use crate::error::{Result, VideobackupError};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+
+
+/// zstd level to compress with, encoding the video takes far longer than even the highest levels anyway
+const ZSTD_LEVEL: i32 = 19;
+
+/// xz preset to compress with, higher ones need a lot more memory for little gain
+const XZ_PRESET: u32 = 6;
+
+/// The algorithms the file can be compressed with before it gets encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Zstd,
+    /// compresses a bit better than zstd, but takes longer
+    Xz,
+}
+
+impl Compression {
+    /// the byte the algorithm is stored as in the metadata
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Compression::Zstd => 0,
+            Compression::Xz => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Compression::Zstd),
+            1 => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = VideobackupError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "zstd" => Ok(Compression::Zstd),
+            "xz" => Ok(Compression::Xz),
+            _ => Err(VideobackupError::InvalidParameters(format!("{} is not a compression algorithm, use zstd, xz or none", s))),
+        }
+    }
+}
+
+/// compresses (input) into (output) a piece at a time so the file never has to fit into memory, returns the size of
+/// the compressed file
+pub fn compress_file(compression: Compression, input: &Path, output: &Path) -> Result<u64> {
+    let mut reader = io::BufReader::new(fs::File::open(input)?);
+    let writer = io::BufWriter::new(fs::File::create(output)?);
+    match compression {
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?.flush()?;
+        },
+        Compression::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(writer, XZ_PRESET);
+            io::copy(&mut reader, &mut encoder)?;
+            encoder.finish()?.flush()?;
+        },
+    }
+    Ok(fs::metadata(output)?.len())
+}
+
+/// undoes compress_file, returns the size of the decompressed file
+pub fn decompress_file(compression: Compression, input: &Path, output: &Path) -> Result<u64> {
+    let reader = io::BufReader::new(fs::File::open(input)?);
+    let mut writer = io::BufWriter::new(fs::File::create(output)?);
+    let size = match compression {
+        Compression::Zstd => io::copy(&mut zstd::Decoder::with_buffer(reader)?, &mut writer)?,
+        Compression::Xz => io::copy(&mut xz2::read::XzDecoder::new(reader), &mut writer)?,
+    };
+    writer.flush()?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TempFile;
+
+    #[test]
+    fn round_trip() {
+        let base = std::env::temp_dir().join(format!("videobackup-rs-test-{}", std::process::id()));
+        let (input, compressed, output) = (TempFile::next_to(&base, "input"), TempFile::next_to(&base, "compressed"), TempFile::next_to(&base, "output"));
+        // repetitive enough to shrink, but not just a single byte over and over
+        let contents: Vec<u8> = (0..200000).map(|i| b"videobackup"[i % 11] ^ (i / 1000) as u8).collect();
+        fs::write(&input.path, &contents).unwrap();
+        for compression in [Compression::Zstd, Compression::Xz] {
+            let size = compress_file(compression, &input.path, &compressed.path).unwrap();
+            assert!(size < contents.len() as u64 / 10, "{} {}", compression, size);
+            assert_eq!(decompress_file(compression, &compressed.path, &output.path).unwrap(), contents.len() as u64);
+            assert_eq!(fs::read(&output.path).unwrap(), contents);
+        }
+        // the other algorithm can't make sense of it
+        assert!(decompress_file(Compression::Zstd, &compressed.path, &output.path).is_err());
+    }
+}
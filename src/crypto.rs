@@ -0,0 +1,407 @@
+extern crate argon2;
+extern crate bech32;
+extern crate chacha20poly1305;
+extern crate hkdf;
+extern crate sha2;
+extern crate x25519_dalek;
+
+use crate::common::random_bytes;
+use crate::error::{Result, VideobackupError};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bech32::{FromBase32, ToBase32, Variant};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+
+
+/// size of the file key and every key that wraps it
+pub const KEY_SIZE: usize = 32;
+
+/// how many bytes encrypting adds to every chunk, that's the authentication tag
+pub const AEAD_OVERHEAD: usize = 16;
+
+pub const SALT_SIZE: usize = 16;
+
+/// the prefixes of X25519 keys, they're written the same way age writes them so keys made with age-keygen work too
+const RECIPIENT_PREFIX: &str = "age";
+const IDENTITY_PREFIX: &str = "age-secret-key-";
+
+/// goes into the derivation of every key that wraps a file key for an X25519 recipient
+const X25519_LABEL: &[u8] = b"videobackup-rs X25519";
+
+/// what a nonce is used for, the same key never sees the same nonce twice
+const NONCE_CHUNK: u8 = 0;
+const NONCE_METADATA: u8 = 1;
+const NONCE_WRAP: u8 = 2;
+
+pub type Key = [u8; KEY_SIZE];
+
+/// the parameters of the Argon2id key derivation, they're stored in the metadata so they can be raised later on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    /// in KiB
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u8,
+}
+
+/// 64MiB and 3 passes, that takes about a second and makes guessing passphrases on GPUs expensive
+pub const DEFAULT_KDF: KdfParams = KdfParams { memory: 64 * 1024, iterations: 3, parallelism: 1 };
+
+/// the most a video may ask of the key derivation, the parameters come from the metadata and a crafted video could
+/// otherwise make us allocate gigabytes or hash for hours. 1GiB and 16 passes leave plenty of room for raising them
+const MAX_KDF: KdfParams = KdfParams { memory: 1024 * 1024, iterations: 16, parallelism: 16 };
+
+/// the encoder writes a single passphrase recipient, more than that only come from a crafted video that wants us to run
+/// the key derivation over and over
+const MAX_PASSPHRASE_RECIPIENTS: usize = 1;
+
+/// How someone who's allowed to decrypt the video gets to the file key, the file key itself is random and
+/// every recipient has a copy of it that's encrypted for them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Recipient {
+    /// the file key encrypted with a key derived from a passphrase
+    Passphrase { salt: [u8; SALT_SIZE], kdf: KdfParams, wrapped_key: Vec<u8> },
+    /// the file key encrypted with a key agreed on between a throwaway X25519 key, whose public half is (share), and
+    /// the public key of the recipient
+    X25519 { share: [u8; KEY_SIZE], wrapped_key: Vec<u8> },
+}
+
+/// The public half of an X25519 key pair, videos encrypted for it can only be decoded with its `X25519Identity`.
+/// It's written like an age recipient, `age1...`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct X25519Recipient(PublicKey);
+
+/// The secret half of an X25519 key pair, written like an age identity, `AGE-SECRET-KEY-1...`.
+#[derive(Clone)]
+pub struct X25519Identity(StaticSecret);
+
+/// the bytes of the bech32 string (s), as long as it starts with (prefix) and holds a key
+fn parse_key(s: &str, prefix: &str) -> Option<[u8; KEY_SIZE]> {
+    let (hrp, data, variant) = bech32::decode(s).ok()?;
+    if hrp != prefix || variant != Variant::Bech32 {
+        return None;
+    }
+    Vec::<u8>::from_base32(&data).ok()?.try_into().ok()
+}
+
+impl FromStr for X25519Recipient {
+    type Err = VideobackupError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match parse_key(s.trim(), RECIPIENT_PREFIX) {
+            Some(v) => Ok(X25519Recipient(PublicKey::from(v))),
+            None => Err(VideobackupError::InvalidParameters(format!("{} is not an X25519 recipient, they look like age1...", s))),
+        }
+    }
+}
+
+impl fmt::Display for X25519Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match bech32::encode(RECIPIENT_PREFIX, self.0.as_bytes().to_base32(), Variant::Bech32) {
+            Ok(v) => write!(f, "{}", v),
+            Err(_) => Err(fmt::Error),
+        }
+    }
+}
+
+impl FromStr for X25519Identity {
+    type Err = VideobackupError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match parse_key(s.trim(), IDENTITY_PREFIX) {
+            Some(v) => Ok(X25519Identity(StaticSecret::from(v))),
+            // the key itself stays out of the error, it's a secret after all
+            None => Err(VideobackupError::InvalidParameters(String::from("That's not an X25519 identity, they look like AGE-SECRET-KEY-1..."))),
+        }
+    }
+}
+
+impl fmt::Debug for X25519Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "X25519Identity({})", self.recipient())
+    }
+}
+
+impl X25519Identity {
+    /// the recipient that videos have to be encrypted for so this identity can decode them
+    pub fn recipient(&self) -> X25519Recipient {
+        X25519Recipient(PublicKey::from(&self.0))
+    }
+
+    /// reads every identity in the identity file (path), which has one per line like the ones age-keygen writes,
+    /// lines starting with # are comments
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<X25519Identity>> {
+        let path = path.as_ref();
+        let identities = fs::read_to_string(path)?.lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(|l| l.parse())
+                .collect::<Result<Vec<X25519Identity>>>()?;
+        if identities.is_empty() {
+            return Err(VideobackupError::InvalidParameters(format!("{} doesn't contain any identities", path.display())));
+        }
+        Ok(identities)
+    }
+}
+
+/// Everything the decoder needs to know about the encryption of a video.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Encryption {
+    /// how many bytes of the file every encrypted chunk holds, every chunk fills exactly one data frame
+    pub chunk_size: u32,
+    pub recipients: Vec<Recipient>,
+    /// the fields of the metadata that would give away anything about the file, encrypted with the file key
+    pub sealed: Vec<u8>,
+}
+
+/// the nonce for (kind) number (index), (last) marks the last chunk so nobody can cut chunks off the end unnoticed.
+/// Every video has its own random file key, so counting up is all it takes to never use a nonce twice
+fn nonce(kind: u8, index: u64, last: bool) -> XNonce {
+    let mut nonce = [0; 24];
+    nonce[0] = kind;
+    nonce[1..=8].copy_from_slice(&index.to_be_bytes());
+    nonce[9] = last as u8;
+    XNonce::from(nonce)
+}
+
+fn encrypt(key: &Key, nonce: &XNonce, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    XChaCha20Poly1305::new(key.into()).encrypt(nonce, Payload { msg: plaintext, aad }).expect("encrypting can only fail for absurdly large chunks")
+}
+
+/// None if (ciphertext) was damaged or encrypted with another key
+fn decrypt(key: &Key, nonce: &XNonce, aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    XChaCha20Poly1305::new(key.into()).decrypt(nonce, Payload { msg: ciphertext, aad }).ok()
+}
+
+pub fn random_key() -> Result<Key> {
+    let mut key = [0; KEY_SIZE];
+    random_bytes(&mut key)?;
+    Ok(key)
+}
+
+/// derives the key that wraps the file key from (passphrase)
+pub fn derive_key(passphrase: &str, salt: &[u8; SALT_SIZE], kdf: KdfParams) -> Result<Key> {
+    if kdf.memory > MAX_KDF.memory || kdf.iterations > MAX_KDF.iterations || kdf.parallelism > MAX_KDF.parallelism {
+        return Err(VideobackupError::CorruptMetadata(format!("the key derivation asks for {} KiB, {} passes and {} lanes, that's more than we allow",
+                                                             kdf.memory, kdf.iterations, kdf.parallelism)));
+    }
+    let params = match Params::new(kdf.memory, kdf.iterations, kdf.parallelism as u32, Some(KEY_SIZE)) {
+        Ok(v) => v,
+        Err(e) => return Err(VideobackupError::CorruptMetadata(format!("the key derivation parameters are not valid: {}", e))),
+    };
+    let mut key = [0; KEY_SIZE];
+    if let Err(e) = Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(passphrase.as_bytes(), salt, &mut key) {
+        return Err(VideobackupError::CorruptMetadata(format!("the key couldn't be derived from the passphrase: {}", e)));
+    }
+    Ok(key)
+}
+
+/// encrypts (file_key) for a passphrase
+pub fn passphrase_recipient(passphrase: &str, file_key: &Key) -> Result<Recipient> {
+    let mut salt = [0; SALT_SIZE];
+    random_bytes(&mut salt)?;
+    let wrapping_key = derive_key(passphrase, &salt, DEFAULT_KDF)?;
+    Ok(Recipient::Passphrase { salt, kdf: DEFAULT_KDF, wrapped_key: encrypt(&wrapping_key, &nonce(NONCE_WRAP, 0, true), &[], file_key) })
+}
+
+/// the key that wraps the file key for (recipient), (shared) is what the recipient and the throwaway key whose public
+/// half is (share) agreed on
+fn x25519_wrapping_key(shared: &[u8; KEY_SIZE], share: &[u8; KEY_SIZE], recipient: &PublicKey) -> Key {
+    let mut salt = share.to_vec();
+    salt.extend_from_slice(recipient.as_bytes());
+    let mut key = [0; KEY_SIZE];
+    Hkdf::<Sha256>::new(Some(&salt), shared).expand(X25519_LABEL, &mut key).expect("a key is never too long for HKDF");
+    key
+}
+
+/// encrypts (file_key) for the holder of the identity of (recipient)
+pub fn x25519_recipient(recipient: &X25519Recipient, file_key: &Key) -> Result<Recipient> {
+    let mut secret = [0; KEY_SIZE];
+    random_bytes(&mut secret)?;
+    let secret = StaticSecret::from(secret);
+    let share = PublicKey::from(&secret).to_bytes();
+    let shared = secret.diffie_hellman(&recipient.0);
+    if !shared.was_contributory() {
+        return Err(VideobackupError::InvalidParameters(format!("{} is not a usable recipient", recipient)));
+    }
+    let wrapping_key = x25519_wrapping_key(shared.as_bytes(), &share, &recipient.0);
+    Ok(Recipient::X25519 { share, wrapped_key: encrypt(&wrapping_key, &nonce(NONCE_WRAP, 0, true), &[], file_key) })
+}
+
+/// gets the file key out of the first recipient (passphrase) or one of (identities) unlocks
+pub fn unlock(recipients: &[Recipient], passphrase: Option<&str>, identities: &[X25519Identity]) -> Result<Key> {
+    // whether there was a recipient we could try to unlock at all, if not we're missing the key rather than having the
+    // wrong one
+    let mut tried = false;
+    let passphrases = recipients.iter().filter(|r| matches!(r, Recipient::Passphrase { .. })).count();
+    if passphrases > MAX_PASSPHRASE_RECIPIENTS {
+        return Err(VideobackupError::CorruptMetadata(format!("there are {} passphrase recipients, the most we allow is {}",
+                                                             passphrases, MAX_PASSPHRASE_RECIPIENTS)));
+    }
+    for recipient in recipients {
+        let wrapping_keys = match recipient {
+            Recipient::Passphrase { salt, kdf, .. } => match passphrase {
+                Some(v) => vec![derive_key(v, salt, *kdf)?],
+                None => Vec::new(),
+            },
+            Recipient::X25519 { share, .. } => identities.iter()
+                    .map(|i| (i.0.diffie_hellman(&PublicKey::from(*share)), i.recipient()))
+                    .filter(|(shared, _)| shared.was_contributory())
+                    .map(|(shared, r)| x25519_wrapping_key(shared.as_bytes(), share, &r.0))
+                    .collect(),
+        };
+        let wrapped_key = match recipient {
+            Recipient::Passphrase { wrapped_key, .. } | Recipient::X25519 { wrapped_key, .. } => wrapped_key,
+        };
+        for wrapping_key in wrapping_keys {
+            tried = true;
+            if let Some(key) = decrypt(&wrapping_key, &nonce(NONCE_WRAP, 0, true), &[], wrapped_key).and_then(|k| k.try_into().ok()) {
+                return Ok(key);
+            }
+        }
+    }
+    Err(if tried { VideobackupError::WrongKey } else { VideobackupError::KeyRequired })
+}
+
+/// what kinds of recipients can unlock a video, for showing it to people
+pub fn describe_recipients(recipients: &[Recipient]) -> String {
+    let passphrases = recipients.iter().filter(|r| matches!(r, Recipient::Passphrase { .. })).count();
+    let x25519 = recipients.len() - passphrases;
+    let mut parts = Vec::new();
+    if passphrases > 0 {
+        parts.push(String::from("passphrase"));
+    }
+    if x25519 > 0 {
+        parts.push(format!("{} X25519 recipient{}", x25519, if x25519 == 1 { "" } else { "s" }));
+    }
+    parts.join(", ")
+}
+
+/// encrypts the fields of the metadata that need to stay secret, (aad) are the ones that don't but mustn't be changed
+pub fn seal_metadata(key: &Key, aad: &[u8], fields: &[u8]) -> Vec<u8> {
+    encrypt(key, &nonce(NONCE_METADATA, 0, true), aad, fields)
+}
+
+pub fn open_metadata(key: &Key, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    decrypt(key, &nonce(NONCE_METADATA, 0, true), aad, sealed)
+}
+
+/// how large the file is once it's cut into chunks of (chunk_size) bytes and every chunk got encrypted
+pub fn encrypted_size(size: u64, chunk_size: usize) -> u64 {
+    size + size.div_ceil(chunk_size as u64) * AEAD_OVERHEAD as u64
+}
+
+/// undoes encrypted_size, a damaged size that's too small for even a single chunk comes out as 0
+pub fn decrypted_size(size: u64, chunk_size: usize) -> u64 {
+    size.saturating_sub(size.div_ceil((chunk_size + AEAD_OVERHEAD) as u64) * AEAD_OVERHEAD as u64)
+}
+
+/// encrypts chunk number (index) of the file, (last) tells whether no other chunk follows it
+pub fn encrypt_chunk(key: &Key, index: u64, last: bool, chunk: &[u8]) -> Vec<u8> {
+    encrypt(key, &nonce(NONCE_CHUNK, index, last), &[], chunk)
+}
+
+/// decrypts the chunks of the encrypted file (input) into (output) one at a time, chunks that were damaged beyond repair
+/// come out as zeros. Returns how many chunks that happened to
+pub fn decrypt_file(key: &Key, chunk_size: usize, input: &Path, output: &Path) -> Result<u64> {
+    let size = fs::metadata(input)?.len();
+    let chunks = size.div_ceil((chunk_size + AEAD_OVERHEAD) as u64);
+    let mut reader = io::BufReader::new(fs::File::open(input)?);
+    let mut writer = io::BufWriter::new(fs::File::create(output)?);
+    let mut buf = vec![0; chunk_size + AEAD_OVERHEAD];
+    let mut damaged = 0;
+    for index in 0..chunks {
+        let length = ((size - index * buf.len() as u64) as usize).min(buf.len());
+        reader.read_exact(&mut buf[0..length])?;
+        match decrypt(key, &nonce(NONCE_CHUNK, index, index == chunks - 1), &[], &buf[0..length]) {
+            Some(v) => writer.write_all(&v)?,
+            None => {
+                writer.write_all(&vec![0; length.saturating_sub(AEAD_OVERHEAD)])?;
+                damaged += 1;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(damaged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_round_trip() {
+        for chunk_size in [1, 100, 4096] {
+            for size in [0, 1, 99, 100, 101, 4096, 4097, 1 << 40] {
+                let encrypted = encrypted_size(size, chunk_size);
+                assert_eq!(decrypted_size(encrypted, chunk_size), size, "{} bytes in chunks of {}", size, chunk_size);
+            }
+        }
+        assert_eq!(encrypted_size(250, 100), 250 + 3 * AEAD_OVERHEAD as u64);
+    }
+
+    #[test]
+    fn damaged_sizes_saturate() {
+        for size in 0..=AEAD_OVERHEAD as u64 {
+            assert_eq!(decrypted_size(size, 100), 0);
+        }
+        assert_eq!(decrypted_size(u64::MAX, 100), u64::MAX - u64::MAX.div_ceil(100 + AEAD_OVERHEAD as u64) * AEAD_OVERHEAD as u64);
+    }
+
+    #[test]
+    fn key_derivation_is_capped() {
+        let salt = [0; SALT_SIZE];
+        for kdf in [KdfParams { memory: MAX_KDF.memory + 1, ..DEFAULT_KDF }, KdfParams { iterations: MAX_KDF.iterations + 1, ..DEFAULT_KDF },
+                    KdfParams { parallelism: MAX_KDF.parallelism + 1, ..DEFAULT_KDF }] {
+            assert!(matches!(derive_key("passphrase", &salt, kdf), Err(VideobackupError::CorruptMetadata(_))));
+        }
+    }
+
+    #[test]
+    fn recipients_unlock() {
+        let file_key = [7; KEY_SIZE];
+        let identity = X25519Identity(StaticSecret::from([8; KEY_SIZE]));
+        // passphrase_recipient would take the default key derivation, a small one keeps the test quick
+        let salt = [9; SALT_SIZE];
+        let kdf = KdfParams { memory: 8, iterations: 1, parallelism: 1 };
+        let wrapping_key = derive_key("passphrase", &salt, kdf).unwrap();
+        let passphrase = Recipient::Passphrase { salt, kdf, wrapped_key: encrypt(&wrapping_key, &nonce(NONCE_WRAP, 0, true), &[], &file_key) };
+        let recipients = [passphrase, x25519_recipient(&identity.recipient(), &file_key).unwrap()];
+
+        assert_eq!(unlock(&recipients, Some("passphrase"), &[]).unwrap(), file_key);
+        assert_eq!(unlock(&recipients, None, &[identity]).unwrap(), file_key);
+        assert!(matches!(unlock(&recipients, Some("wrong"), &[]), Err(VideobackupError::WrongKey)));
+        assert!(matches!(unlock(&recipients, None, &[]), Err(VideobackupError::KeyRequired)));
+    }
+
+    #[test]
+    fn passphrase_recipients_are_capped() {
+        // the key derivation of these would take far too long if they were ever tried
+        let passphrase = Recipient::Passphrase { salt: [9; SALT_SIZE], kdf: MAX_KDF, wrapped_key: vec![0; KEY_SIZE + AEAD_OVERHEAD] };
+        let recipients = vec![passphrase; MAX_PASSPHRASE_RECIPIENTS + 1];
+        assert!(matches!(unlock(&recipients, Some("passphrase"), &[]), Err(VideobackupError::CorruptMetadata(_))));
+        assert!(matches!(unlock(&recipients, None, &[]), Err(VideobackupError::CorruptMetadata(_))));
+    }
+
+    #[test]
+    fn chunks_are_bound_to_their_place() {
+        let key = [1; KEY_SIZE];
+        let chunk = encrypt_chunk(&key, 3, false, b"hello");
+        assert_eq!(chunk.len(), 5 + AEAD_OVERHEAD);
+        assert_eq!(decrypt(&key, &nonce(NONCE_CHUNK, 3, false), &[], &chunk).unwrap(), b"hello");
+        assert!(decrypt(&key, &nonce(NONCE_CHUNK, 4, false), &[], &chunk).is_none());
+        assert!(decrypt(&key, &nonce(NONCE_CHUNK, 3, true), &[], &chunk).is_none());
+    }
+}
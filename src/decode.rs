@@ -1,294 +1,1325 @@
-extern crate image;
+extern crate crc32fast;
+extern crate num_cpus;
 extern crate path_absolutize;
 extern crate reed_solomon;
 
+use crate::archive;
 use crate::common::BLOCK_SIZE;
-use crate::common::ENCODING_VERSION;
+use crate::calibration::Calibration;
+use crate::common::{deinterleave, metadata_offsets, FrameLayout, MAX_PIXEL_SIZE};
 use crate::common::crc32_file;
+use crate::error::{Result, VideobackupError};
 use crate::common::zero_vec;
+use crate::common::TempFile;
+use crate::compression::decompress_file;
+use crate::crypto;
+use crate::crypto::{X25519Identity, AEAD_OVERHEAD};
+use crate::ffmpeg;
+use crate::ffmpeg::FfmpegReader;
+use crate::geometry::Geometry;
+use crate::hash::{hash_file, to_hex};
+use crate::header::{FrameHeader, FRAME_HEADER_SIZE};
+use crate::metadata::{format_timestamp, versions, Metadata, METADATA_BLOCK_SIZE};
+use crate::palette::{Palette, PaletteFamily};
+use crate::parity;
 
-use reed_solomon::Decoder;
+use reed_solomon::Buffer;
+use reed_solomon::Decoder as EccDecoder;
+use reed_solomon::DecoderError;
 
-use image::{GenericImageView};
+use path_absolutize::Absolutize;
 
-use std::convert::TryInto;
+use std::collections::HashMap;
 use std::fs;
 use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use std::time::Instant;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 
-/// extracts (amount) frames from the input video starting at (start)
-fn get_frames(input: &str, start: u64, amount: u64) -> Vec<PathBuf> {
-    let extract_res = Command::new("ffmpeg")
-                              .args(&["-i", input, "-vf", &format!("select='between(n\\,{}\\,{})'", start, start+amount-1), "-vsync", "0", Path::new("tmp").join("%04d.png").to_str().unwrap()])
-                              .output().unwrap();
-    let mut return_vec: Vec<PathBuf> = Vec::with_capacity(amount as usize);
-    // TODO: make that for loop more compact
-    for i in 1..(amount+1) {
-        return_vec.push(Path::new("tmp").join(format!("{:0>4}.png", i)));
+/// Options for decoding a video back into a file. Start with `DecodeOptions::new()` and chain the setters.
+#[derive(Clone, Debug)]
+pub struct DecodeOptions {
+    checksum: bool,
+    cluster_palette: bool,
+    threads: usize,
+    output_dir: PathBuf,
+    passphrase: Option<String>,
+    identities: Vec<X25519Identity>,
+    extract: Option<String>,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            checksum: true,
+            cluster_palette: false,
+            threads: num_cpus::get(),
+            output_dir: PathBuf::from("."),
+            passphrase: None,
+            identities: Vec::new(),
+            extract: None,
+        }
+    }
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// whether to verify the CRC32 of the decoded file
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// whether to learn the palette of every frame with k-means when the video has no calibration frame, which helps
+    /// when the colors shifted after the video got re-encoded
+    pub fn cluster_palette(mut self, cluster_palette: bool) -> Self {
+        self.cluster_palette = cluster_palette;
+        self
+    }
+
+    /// how many threads to use
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// the directory the decoded file is written to, the file name is taken from the metadata
+    pub fn output_dir<P: AsRef<Path>>(mut self, output_dir: P) -> Self {
+        self.output_dir = output_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// the passphrase to decrypt encrypted videos with, decoding one without it or an identity that's one of its
+    /// recipients fails with `VideobackupError::KeyRequired`
+    pub fn passphrase<S: Into<String>>(mut self, passphrase: Option<S>) -> Self {
+        self.passphrase = passphrase.map(|p| p.into());
+        self
+    }
+
+    /// the identities to decrypt videos that were encrypted for X25519 recipients with
+    pub fn identities(mut self, identities: Vec<X25519Identity>) -> Self {
+        self.identities = identities;
+        self
+    }
+
+    /// only extract (member) out of a video of a directory, its path is relative to the directory and it keeps that
+    /// path in the output directory. None extracts everything
+    pub fn extract<S: Into<String>>(mut self, member: Option<S>) -> Self {
+        self.extract = member.map(|m| m.into());
+        self
+    }
+}
+
+/// Turns videos back into files according to its `DecodeOptions`.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    options: DecodeOptions,
+}
+
+/// a read data frame along with some metrics
+struct ReadFrame {
+    /// where the frame belongs in the file, None if its frame header couldn't be read
+    sequence: Option<u64>,
+    /// whether the frame header says the frame belongs to a different archive
+    foreign: bool,
+    /// the content bytes of the frame after error correction
+    bytes: Vec<u8>,
+    /// whether the content matches the CRC32 in the frame header, without frame headers it's only known whether the ECC worked
+    intact: bool,
+    correct_pixels: u128,
+    estimated_pixels: u128,
+    ecced_bytes: u64,
+    unrecoverable_blocks: u64,
+    /// how far the palette learned with k-means is off from the ideal one
+    palette_drift: Option<f32>,
+}
+
+/// where read_raw_frame gets the colors of the palette from
+#[derive(Clone, Copy)]
+enum PaletteSource<'a> {
+    Ideal,
+    /// learned from the calibration frame
+    Calibrated(&'a Calibration),
+    /// learned from every frame on its own with k-means
+    Clustered,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FrameState {
+    Missing,
+    Damaged,
+    Intact,
+}
+
+/// keeps track of which data frames we've already written and of the frames we couldn't use
+struct FrameStates {
+    states: Vec<FrameState>,
+    unreadable: u64,
+    foreign: u64,
+    duplicates: u64,
+}
+
+impl FrameStates {
+    /// tells the user about everything that went wrong with the frames
+    fn report(&self) {
+        let missing: Vec<u64> = (0..self.states.len() as u64).filter(|&i| self.states[i as usize] == FrameState::Missing).collect();
+        let damaged: Vec<u64> = (0..self.states.len() as u64).filter(|&i| self.states[i as usize] == FrameState::Damaged).collect();
+        if self.duplicates > 0 {
+            println!("→ Skipped {} duplicated frames", self.duplicates);
+        }
+        if self.foreign > 0 {
+            println!("⚠ Skipped {} frames that belong to a different archive", self.foreign);
+        }
+        if self.unreadable > 0 {
+            println!("⚠ {} frames were so damaged that it's unknown where they belong", self.unreadable);
+        }
+        if !missing.is_empty() {
+            println!("⚠ Missing data frames: {}", format_ranges(&missing));
+        }
+        if !damaged.is_empty() {
+            println!("⚠ Damaged data frames: {}", format_ranges(&damaged));
+        }
     }
-    return return_vec;
 }
 
-/// deletes the temporarily extracted frames
-fn delete_frames(amount: u64) {
-    for i in 1..(amount+1) {
-        fs::remove_file(Path::new("tmp").join(format!("{:0>4}.png", i))).unwrap();
+/// turns a sorted list of frame numbers into something like "3, 7-9, 12"
+fn format_ranges(numbers: &[u64]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < numbers.len() {
+        let start = numbers[i];
+        while i + 1 < numbers.len() && numbers[i + 1] == numbers[i] + 1 {
+            i += 1;
+        }
+        if numbers[i] == start {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{}-{}", start, numbers[i]));
+        }
+        i += 1;
+    }
+    ranges.join(", ")
+}
+
+/// writes the read data frames to where they belong in the file and keeps the parity frames around until we know
+/// whether we need them
+struct FrameWriter {
+    file: fs::File,
+    states: FrameStates,
+    file_size: u64,
+    content_bytes_per_frame: usize,
+    data_frames: u64,
+    parity_group: u64,
+    parity_frames: u64,
+    /// the intact parity frames of every group that isn't complete yet, by group
+    parity: HashMap<u64, Vec<Option<Vec<u8>>>>,
+    correct_pixels: u128,
+    estimated_pixels: u128,
+    ecced_bytes: u64,
+    unrecoverable_blocks: u64,
+    /// the sum and the largest of the palette drifts of all clustered frames
+    palette_drift: (f64, f32),
+    clustered_frames: u64,
+}
+
+impl FrameWriter {
+    /// the data frames that make up (group)
+    fn group_range(&self, group: u64) -> std::ops::Range<u64> {
+        (group * self.parity_group)..((group + 1) * self.parity_group).min(self.data_frames)
+    }
+
+    fn group_complete(&self, group: u64) -> bool {
+        self.group_range(group).all(|i| self.states.states[i as usize] == FrameState::Intact)
+    }
+
+    fn write(&mut self, read: ReadFrame) -> Result<()> {
+        self.correct_pixels += read.correct_pixels;
+        self.estimated_pixels += read.estimated_pixels;
+        if let Some(drift) = read.palette_drift {
+            self.palette_drift = (self.palette_drift.0 + drift as f64, self.palette_drift.1.max(drift));
+            self.clustered_frames += 1;
+        }
+        if read.foreign {
+            self.states.foreign += 1;
+            return Ok(());
+        }
+        let sequence = match read.sequence {
+            Some(v) if v >= self.data_frames => {
+                self.store_parity(v - self.data_frames, read);
+                return Ok(());
+            },
+            Some(v) => v,
+            None => {
+                self.states.unreadable += 1;
+                return Ok(());
+            }
+        };
+        // we keep the first intact copy of every frame, a damaged copy only gets replaced by an intact one
+        match (self.states.states[sequence as usize], read.intact) {
+            (FrameState::Intact, _) | (FrameState::Damaged, false) => {
+                self.states.duplicates += 1;
+                return Ok(());
+            },
+            _ => (),
+        }
+        self.states.states[sequence as usize] = if read.intact { FrameState::Intact } else { FrameState::Damaged };
+        self.ecced_bytes += read.ecced_bytes;
+        self.unrecoverable_blocks += read.unrecoverable_blocks;
+        self.write_at(sequence, &read.bytes)?;
+
+        // once a group is complete its parity frames are useless
+        if self.parity_frames > 0 && self.group_complete(sequence / self.parity_group) {
+            self.parity.remove(&(sequence / self.parity_group));
+        }
+        Ok(())
+    }
+
+    /// (index) counts the parity frames from the first one, frames past the last parity frame are just padding
+    fn store_parity(&mut self, index: u64, read: ReadFrame) {
+        if self.parity_frames == 0 || !read.intact {
+            return;
+        }
+        let group = index / self.parity_frames;
+        if group >= self.data_frames.div_ceil(self.parity_group) || self.group_complete(group) {
+            return;
+        }
+        let parity_frames = self.parity_frames as usize;
+        let stored = self.parity.entry(group).or_insert_with(|| vec![None; parity_frames]);
+        let slot = &mut stored[(index % self.parity_frames) as usize];
+        if slot.is_some() {
+            self.states.duplicates += 1;
+        } else {
+            *slot = Some(read.bytes);
+        }
+    }
+
+    fn write_at(&mut self, sequence: u64, bytes: &[u8]) -> Result<()> {
+        // the last frame probably contains a bunch of useless NULs
+        let position = sequence * self.content_bytes_per_frame as u64;
+        let length = (bytes.len() as u64).min(self.file_size - position) as usize;
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.write_all(&bytes[0..length])?;
+        Ok(())
+    }
+
+    /// reads an already written data frame back from the file, padded with zeros just like the encoder did
+    fn read_at(&mut self, sequence: u64) -> Result<Vec<u8>> {
+        let position = sequence * self.content_bytes_per_frame as u64;
+        let length = (self.content_bytes_per_frame as u64).min(self.file_size - position) as usize;
+        let mut bytes = zero_vec(self.content_bytes_per_frame);
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.read_exact(&mut bytes[0..length])?;
+        Ok(bytes)
+    }
+
+    /// tries to reconstruct the missing and damaged data frames of every group from its parity frames,
+    /// returns how many frames were recovered
+    fn recover(&mut self) -> Result<u64> {
+        let mut recovered: u64 = 0;
+        let mut groups: Vec<u64> = self.parity.keys().copied().collect();
+        groups.sort_unstable();
+        for group in groups {
+            if self.group_complete(group) {
+                continue;
+            }
+            let mut frames: Vec<Option<Vec<u8>>> = Vec::with_capacity((self.parity_group + self.parity_frames) as usize);
+            for i in (group * self.parity_group)..((group + 1) * self.parity_group) {
+                if i >= self.data_frames {
+                    // the last group got padded with frames full of zeros
+                    frames.push(Some(zero_vec(self.content_bytes_per_frame)));
+                } else if self.states.states[i as usize] == FrameState::Intact {
+                    frames.push(Some(self.read_at(i)?));
+                } else {
+                    frames.push(None);
+                }
+            }
+            frames.extend(self.parity.remove(&group).unwrap());
+            if !parity::reconstruct(&mut frames, self.parity_group as usize, self.parity_frames as usize) {
+                println!("⚠ Too many frames of parity group {} are missing or damaged to reconstruct them", group);
+                continue;
+            }
+            for i in self.group_range(group) {
+                if self.states.states[i as usize] != FrameState::Intact {
+                    let bytes = frames[(i - group * self.parity_group) as usize].take().unwrap();
+                    self.write_at(i, &bytes)?;
+                    self.states.states[i as usize] = FrameState::Intact;
+                    recovered += 1;
+                }
+            }
+        }
+        Ok(recovered)
     }
 }
 
-/// failsafe for trying to read colors that aren't in the color palette
-fn try_read_pixel(color: [u8; 3], color_palette: &Vec<[u8; 3]>) -> (usize, bool) {
+/// the confidence of a pixel whose color is exactly in the color palette
+const EXACT: u32 = u32::MAX;
+
+/// failsafe for trying to read colors that aren't in the color palette, returns the index of the color and how confident
+/// we are about it: EXACT if the color is in the color palette, otherwise how much closer the nearest color is than the
+/// second nearest one
+fn try_read_pixel(color: [u8; 3], color_palette: &[[u8; 3]]) -> (usize, u32) {
     let index = color_palette.iter().position(|&r| r == color);
     match index {
         // the color is actually in the color palette, return that
-        Some(v) => { return (v, true); },
+        Some(v) => (v, EXACT),
         // the color isn't in the color palette, search the color in the color palette nearest to our color
         None => {
-            // TODO: make this more efficient
-            // we create a vector (not a hashmap because we need sorting) where we map each color of the color palette
-            // to the distance from the color we read, sort it and then look what color is closest
-            let mut unsorted_distances: Vec<(usize, i32)> = Vec::with_capacity(color_palette.len());
-            for i in 0..color_palette.len() {
-                let distance: i32 = ((color[0] as i32 - color_palette[i][0] as i32) * (color[0] as i32 - color_palette[i][0] as i32))
-                                  + ((color[1] as i32 - color_palette[i][1] as i32) * (color[1] as i32 - color_palette[i][1] as i32))
-                                  + ((color[2] as i32 - color_palette[i][2] as i32) * (color[2] as i32 - color_palette[i][2] as i32));
-                unsorted_distances.push((i, distance));
+            // remember the nearest and the second nearest color, we need the second one for the confidence
+            let mut nearest: (usize, i32) = (0, i32::MAX);
+            let mut second_distance = i32::MAX;
+            for (i, palette_color) in color_palette.iter().enumerate() {
+                let distance: i32 = ((color[0] as i32 - palette_color[0] as i32) * (color[0] as i32 - palette_color[0] as i32))
+                                  + ((color[1] as i32 - palette_color[1] as i32) * (color[1] as i32 - palette_color[1] as i32))
+                                  + ((color[2] as i32 - palette_color[2] as i32) * (color[2] as i32 - palette_color[2] as i32));
+                if distance < nearest.1 {
+                    second_distance = nearest.1;
+                    nearest = (i, distance);
+                } else if distance < second_distance {
+                    second_distance = distance;
+                }
             }
-            unsorted_distances.sort_by(|a, b| a.1.cmp(&b.1));
-            return (unsorted_distances[0].0, false);
+            (nearest.0, (second_distance - nearest.1) as u32)
         }
     }
 }
 
-/// tries to read a single frame
-fn read_raw_frame(input: &str, colors: u16) -> (Vec<u8>, u128, u128, u32, u32) {
-    let two_color_palette: Vec<[u8; 3]> = vec![[0, 0, 0], [255, 255, 255]]; 
-    let four_color_palette: Vec<[u8; 3]> = vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+/// a raw frame turned back into bytes
+struct RawFrame {
+    bytes: Vec<u8>,
+    /// the confidence of every byte, which is the confidence of its least confident pixel
+    confidence: Vec<u32>,
+    correct_pixels: u128,
+    estimated_pixels: u128,
+}
+
+/// the color of the cell with the given number, cells larger than 2x2 pixels only get their inner pixels averaged since
+/// compression likes to smear the colors of neighboring cells into their edges
+fn read_cell(frame: &[u8], layout: FrameLayout, cell: usize) -> [u8; 3] {
+    let pixel_size = layout.pixel_size as usize;
+    let (x, y) = layout.cell_position(cell);
+    if pixel_size == 1 {
+        let pixel = (y * layout.width + x) * 3;
+        return [frame[pixel], frame[pixel + 1], frame[pixel + 2]];
+    }
+    let border = if pixel_size > 2 { 1 } else { 0 };
+    let mut sum: [u32; 3] = [0; 3];
+    for row in (y + border)..(y + pixel_size - border) {
+        for column in (x + border)..(x + pixel_size - border) {
+            let pixel = (row * layout.width + column) * 3;
+            for c in 0..3 {
+                sum[c] += frame[pixel + c] as u32;
+            }
+        }
+    }
+    let count = ((pixel_size - 2 * border) * (pixel_size - 2 * border)) as u32;
+    [((sum[0] + count / 2) / count) as u8, ((sum[1] + count / 2) / count) as u8, ((sum[2] + count / 2) / count) as u8]
+}
+
+/// tries to read a single raw RGB24 frame, with the palette learned from the calibration frame if there is one
+fn read_raw_frame(frame: &[u8], layout: FrameLayout, calibration: Option<&Calibration>) -> RawFrame {
+    let palette = Palette::new(layout.colors, layout.palette);
+    let bits = palette.bits();
 
     let mut correct_pixels: u128 = 0;
     let mut estimated_pixels: u128 = 0;
 
-    let img = image::open(input).unwrap();
+    let capacity = layout.bytes();
+    let mut buf: Vec<u8> = Vec::with_capacity(capacity);
+    let mut confidence: Vec<u32> = Vec::with_capacity(capacity);
+
+    // every cell holds the next (bits) bits of one long stream of bits, we collect them until we've got a full byte
+    let mut pending: u32 = 0;
+    let mut pending_bits: u32 = 0;
+    let mut current_confidence: u32 = EXACT;
 
-    let mut buf: Vec<u8> = Vec::with_capacity((((img.dimensions().0 * img.dimensions().1) as f32) / (colors as f32).log(256.0)) as usize);
+    for i in 0..layout.cells() {
+        let colors = match calibration {
+            Some(v) => v.palette(i),
+            None => palette.colors(),
+        };
+        let read_pixel = try_read_pixel(read_cell(frame, layout, i), colors);
+        pending = (pending << bits) | read_pixel.0 as u32;
+        pending_bits += bits;
+        current_confidence = current_confidence.min(read_pixel.1);
+        while pending_bits >= 8 {
+            pending_bits -= 8;
+            buf.push((pending >> pending_bits) as u8);
+            confidence.push(current_confidence);
+            // a cell can hold bits of two bytes
+            current_confidence = if pending_bits > 0 { read_pixel.1 } else { EXACT };
+        }
+        pending &= (1 << pending_bits) - 1;
+        if read_pixel.1 == EXACT {
+            correct_pixels += 1;
+        } else {
+            estimated_pixels += 1;
+        }
+    }
+    RawFrame { bytes: buf, confidence, correct_pixels, estimated_pixels }
+}
 
-    let mut current_byte: u8 = 0;
+/// the positions of the least confident bytes of a block, which the ECC can treat as erasures: it only needs one
+/// ECC byte to fix an erasure instead of two for an error it has to find first. At most half of the ECC bytes go to
+/// erasures, the rest is left for the errors that were read with confidence
+fn erasures(confidence: &[u32], ecc_count: u8) -> Vec<u8> {
+    let mut guessed: Vec<(u8, u32)> = confidence.iter().enumerate()
+            .filter(|(_, &c)| c != EXACT)
+            .map(|(i, &c)| (i as u8, c))
+            .collect();
+    guessed.sort_by_key(|g| g.1);
+    guessed.truncate((ecc_count / 2) as usize);
+    guessed.into_iter().map(|g| g.0).collect()
+}
 
-    for (i, pixel) in img.pixels().enumerate() {
-        let read_color = [pixel.2[0], pixel.2[1], pixel.2[2]];
-        if colors == 2 {
-            let read_pixel = try_read_pixel(read_color, &two_color_palette);
-            // OR the read bit (since we're in 2 color mode) with the currently read byte
-            current_byte = current_byte | ((read_pixel.0 as u8) << (7 - (i % 8)));
-            if i % 8 == 7 {
-                // we've written all 8 bits for our byte, push it to the buffer and start reading
-                // a new one next time
-                buf.push(current_byte);
-                current_byte = 0;
-            }
-            if read_pixel.1 {
-                correct_pixels += 1;
-            } else {
-                estimated_pixels += 1;
-            }
-        } else if colors == 4 {
-            let read_pixel = try_read_pixel(read_color, &four_color_palette);
-            // OR the read 2 bits (since we're in 4 color mode) with the currently read byte
-            current_byte = current_byte | ((read_pixel.0 as u8) << (6 - ((i % 4) * 2)));
-            if i % 4 == 3 {
-                // we've written all 8 bits for our byte, push it to the buffer and start reading
-                // a new one next time
-                buf.push(current_byte);
-                current_byte = 0;
-            }
-            if read_pixel.1 {
-                correct_pixels += 1;
-            } else {
-                estimated_pixels += 1;
-            }
+/// corrects (block) with the given erasures, if that doesn't work out we might have guessed wrong about which bytes
+/// are broken and it gets corrected without them
+fn correct_with_erasures(decoder: &EccDecoder, block: &[u8], erasures: &[u8]) -> std::result::Result<(Buffer, usize), DecoderError> {
+    // the reed-solomon crate divides by zero if a position is erased twice and indexes out of bounds for positions
+    // outside of the block
+    let mut seen = [false; 256];
+    let erasures: Vec<u8> = erasures.iter().copied()
+            .filter(|&e| (e as usize) < block.len() && !std::mem::replace(&mut seen[e as usize], true))
+            .collect();
+    if !erasures.is_empty() {
+        if let Ok(v) = decoder.correct_err_count(block, Some(&erasures)) {
+            return Ok(v);
         }
     }
-    return (buf, correct_pixels, estimated_pixels, img.dimensions().0, img.dimensions().1);
+    decoder.correct_err_count(block, None)
 }
 
-/// This function calls read_raw_frame and then handles the ECC stuff
-fn read_frame(frame: &str, colors: u16, ecc_count: u8, blocks_per_frame: usize, number: u64) -> (Vec<u8>, u128, u128, u64, u64) {
+/// This function calls read_raw_frame, checks the frame header (if the archive has them) and then handles the ECC stuff,
+/// (number) is the position of the frame in the video, not counting the metadata frame
+fn read_frame(frame: &[u8], layout: FrameLayout, source: PaletteSource, ecc_count: u8, blocks_per_frame: usize, archive_id: Option<[u8; 16]>, number: u64) -> ReadFrame {
     let content_bytes_per_block: usize = (BLOCK_SIZE - ecc_count) as usize;
     let content_bytes_per_frame = blocks_per_frame * content_bytes_per_block;
-    let mut buf: Vec<u8> = Vec::with_capacity(blocks_per_frame * content_bytes_per_block as usize);
+    let mut buf: Vec<u8> = Vec::with_capacity(blocks_per_frame * content_bytes_per_block);
 
-    let decoder = Decoder::new(ecc_count as usize);
+    let decoder = EccDecoder::new(ecc_count as usize);
     let mut ecced_bytes: u64 = 0;
     let mut unrecoverable_blocks: u64 = 0;
 
-    let frame = read_raw_frame(frame, colors);
-    
+    let clustered: Calibration;
+    let mut palette_drift = None;
+    let calibration = match source {
+        PaletteSource::Ideal => None,
+        PaletteSource::Calibrated(v) => Some(v),
+        PaletteSource::Clustered => {
+            clustered = Calibration::cluster(layout, |cell| read_cell(frame, layout, cell));
+            palette_drift = Some(clustered.drift(&Palette::new(layout.colors, layout.palette)));
+            Some(&clustered)
+        }
+    };
+    let mut frame = read_raw_frame(frame, layout, calibration);
+
+    let mut read = ReadFrame {
+        sequence: Some(number),
+        foreign: false,
+        bytes: Vec::new(),
+        intact: true,
+        correct_pixels: frame.correct_pixels,
+        estimated_pixels: frame.estimated_pixels,
+        ecced_bytes: 0,
+        unrecoverable_blocks: 0,
+        palette_drift,
+    };
+
+    // without a frame header we have to trust that the frame sits where it's supposed to be
+    let mut header: Option<FrameHeader> = None;
+    let mut offset = 0;
+    if let Some(archive_id) = archive_id {
+        offset = FRAME_HEADER_SIZE;
+        match FrameHeader::from_bytes(&frame.bytes) {
+            Some(v) if v.archive_id != archive_id => {
+                read.foreign = true;
+                return read;
+            },
+            Some(v) => {
+                read.sequence = Some(v.sequence as u64);
+                header = Some(v);
+            },
+            None => {
+                read.sequence = None;
+                return read;
+            }
+        }
+    }
+    let sequence = read.sequence.unwrap_or(number);
+
+    if layout.interleaved {
+        let blocks_end = offset + blocks_per_frame * BLOCK_SIZE as usize;
+        let bytes = deinterleave(&frame.bytes[offset..blocks_end], blocks_per_frame);
+        frame.bytes[offset..blocks_end].copy_from_slice(&bytes);
+        let confidence = deinterleave(&frame.confidence[offset..blocks_end], blocks_per_frame);
+        frame.confidence[offset..blocks_end].copy_from_slice(&confidence);
+    }
+
     for i in 0..blocks_per_frame {
-        let current_block = &frame.0[(i * BLOCK_SIZE as usize)..((i+1) * BLOCK_SIZE as usize)];
-        let decoded_bytes = decoder.correct_err_count(current_block, None);
+        let block_range = (offset + i * BLOCK_SIZE as usize)..(offset + (i+1) * BLOCK_SIZE as usize);
+        let current_block = &frame.bytes[block_range.clone()];
+        let erasures = erasures(&frame.confidence[block_range], ecc_count);
+        let decoded_bytes = correct_with_erasures(&decoder, current_block, &erasures);
         match decoded_bytes {
             Ok(v) => {
                 buf.extend_from_slice(v.0.data());
                 ecced_bytes += v.1 as u64;
             },
-            Err(e) => {
+            Err(_) => {
                 buf.extend_from_slice(&current_block[0..content_bytes_per_block]);
-                println!("⚠ WARNING: Encountered an unrecoverable data block starting at {:#X} and ending at (not including) {:#X}. The block will be inserted without any error correction, your file is very likely to be damaged.", 
-                         (number * content_bytes_per_frame as u64 + i as u64 * content_bytes_per_block as u64),
-                         (number * content_bytes_per_frame as u64 + (i+1) as u64 * content_bytes_per_block as u64));
+                println!("⚠ WARNING: Encountered an unrecoverable data block starting at {:#X} and ending at (not including) {:#X}. The block will be inserted without any error correction, your file is very likely to be damaged.",
+                         (sequence * content_bytes_per_frame as u64 + i as u64 * content_bytes_per_block as u64),
+                         (sequence * content_bytes_per_frame as u64 + (i+1) as u64 * content_bytes_per_block as u64));
                 unrecoverable_blocks += 1;
             }
         }
     }
-    return (buf, frame.1, frame.2, ecced_bytes, unrecoverable_blocks);
+
+    read.intact = match header {
+        Some(v) => crc32fast::hash(&buf) == v.crc32,
+        None => unrecoverable_blocks == 0,
+    };
+    read.bytes = buf;
+    read.ecced_bytes = ecced_bytes;
+    read.unrecoverable_blocks = unrecoverable_blocks;
+    read
+}
+
+/// error corrects a single copy of the metadata of (version), (accept) tells whether the metadata makes any sense
+fn correct_metadata_copy<F: Fn(&Metadata) -> bool>(copy: &[u8], version: u16, accept: &F) -> Result<Option<Metadata>> {
+    match Metadata::read(copy, version) {
+        Some(Ok(v)) if accept(&v) => Ok(Some(v)),
+        Some(Err(e)) => Err(e),
+        _ => Ok(None),
+    }
 }
 
-pub fn decode(input: &str, checksum: bool, threads: usize) {
-    let start_time = Instant::now();
+/// tries to error correct every copy of the metadata on its own first, if none of them can be corrected every byte
+/// gets voted on by all copies, since damage rarely hits the same byte of every copy. Returns the metadata, its encoding
+/// version and whether it had to be voted on. A copy that can be corrected but not read, e.g. because it's of an
+/// unknown version, doesn't stop us from looking at the others, its error is only returned if none of them can be read
+fn correct_metadata<F: Fn(&Metadata) -> bool>(frames: &[RawFrame], accept: F) -> Result<Option<(Metadata, u16, bool)>> {
+    let mut error: Option<VideobackupError> = None;
+    // we don't know the version and size of the metadata before we read it, and they decide where its copies are
+    let candidates: Vec<(u16, usize)> = versions().into_iter()
+            .flat_map(|v| Metadata::copy_sizes(v).into_iter().map(move |s| (v, s)))
+            .collect();
+    for &(version, size) in candidates.iter() {
+        for copy in metadata_copies(frames, size) {
+            match correct_metadata_copy(copy, version, &accept) {
+                Ok(Some(v)) => return Ok(Some((v, version, false))),
+                Ok(None) => (),
+                Err(e) => {
+                    error.get_or_insert(e);
+                },
+            }
+        }
+    }
+    for &(version, size) in candidates.iter() {
+        let copies = metadata_copies(frames, size);
+        if copies.len() < 3 {
+            continue;
+        }
+        let length = copies.iter().map(|c| c.len()).min().unwrap();
+        let voted: Vec<u8> = (0..length).map(|i| {
+            let mut votes: [usize; 256] = [0; 256];
+            for copy in copies.iter() {
+                votes[copy[i] as usize] += 1;
+            }
+            // ties go to the byte of the earliest copy
+            copies.iter().map(|c| c[i]).max_by_key(|&b| (votes[b as usize], std::cmp::Reverse(copies.iter().position(|c| c[i] == b)))).unwrap()
+        }).collect();
+        match correct_metadata_copy(&voted, version, &accept) {
+            Ok(Some(v)) => return Ok(Some((v, version, true))),
+            Ok(None) => (),
+            Err(e) => {
+                error.get_or_insert(e);
+            },
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
 
-    // create temp folder for saving the PNG and TS files
-    std::fs::create_dir_all(Path::new("tmp")).unwrap();
+/// all copies of the metadata in the metadata frames (frames) if it's (size) bytes long, read as raw bytes. Frames that
+/// are too small for a whole copy have a shorter one, which only older versions made use of
+fn metadata_copies(frames: &[RawFrame], size: usize) -> Vec<&[u8]> {
+    frames.iter()
+            .flat_map(|f| metadata_offsets(f.bytes.len(), size).into_iter().map(move |o| &f.bytes[o..(o + size).min(f.bytes.len())]))
+            .filter(|c| c.len() >= METADATA_BLOCK_SIZE)
+            .collect()
+}
 
-    // we want to have some metrics for the end
-    let mut correct_pixels: u128 = 0;
-    let mut estimated_pixels: u128 = 0;
-    let mut ecced_bytes: u64 = 0;
-    let mut unrecoverable_blocks: u64 = 0;
+/// the metadata and how it was found
+struct FoundMetadata {
+    metadata: Metadata,
+    /// the encoding version of the video
+    version: u16,
+    /// whether no copy could be corrected on its own, so the copies had to be voted on
+    voted: bool,
+    /// where the cells are if the frames have fiducials, they get resampled before they're read
+    geometry: Option<Geometry>,
+    /// the layout the metadata frame got read with
+    layout: FrameLayout,
+}
 
-    println!("→ Starting videobackup-rs decoder");
+/// looks for the metadata in the (width)x(height) metadata frames (frames), that's the first frame of the video and
+/// maybe the copy at the end of it
+fn find_metadata(frames: &[&[u8]], width: usize, height: usize) -> Result<Option<FoundMetadata>> {
+    // like in correct_metadata, a layout whose metadata can't be read doesn't stop us from trying the others
+    let mut error: Option<VideobackupError> = None;
+    // if the frames have fiducials they tell us where their cells are
+    let geometries: Vec<Option<Geometry>> = frames.iter().map(|f| Geometry::locate(f, width, height)).collect();
+    if let Some(geometry) = geometries.iter().flatten().next().copied() {
+        let layout = FrameLayout { width: geometry.columns, height: geometry.rows, colors: 2, palette: PaletteFamily::Color, pixel_size: 1, interleaved: false, fiducials: true };
+        let raw_frames: Vec<RawFrame> = frames.iter().zip(geometries.iter())
+                .map(|(f, g)| {
+                    let g = g.filter(|g| (g.columns, g.rows) == (geometry.columns, geometry.rows)).unwrap_or(geometry);
+                    read_raw_frame(&g.resample(f), layout, None)
+                })
+                .collect();
+        match correct_metadata(&raw_frames, |_| true) {
+            Ok(Some((metadata, version, voted))) => return Ok(Some(FoundMetadata { metadata, version, voted, geometry: Some(geometry), layout })),
+            Ok(None) => (),
+            Err(e) => {
+                error.get_or_insert(e);
+            },
+        }
+    }
 
-    // get the number of frames in the video
-    println!("→ Counting frames in video...");
-    let mut frames_amount: u64 = 0;
-    let first_ffprobe_res = Command::new("ffprobe")
-                           .args(&["-v", "error", "-select_streams", "v:0", "-show_entries", "stream=nb_frames", "-of", "default=nokey=1:noprint_wrappers=1", input])
-                           .output().unwrap();
-    let first_ffprobe_int = String::from_utf8(first_ffprobe_res.stdout).unwrap().replace("\n", "").parse::<u64>();
-    match first_ffprobe_int {
-        Ok(v) => { frames_amount = v },
-        Err(e) => {
-            println!("→ Unable to use container information to get frames count, resorting to manually counting (this will take a while)...");
-            let second_ffprobe_res = Command::new("ffprobe")
-                                    .args(&["-v", "error", "-count_frames", "-select_streams", "v:0", "-show_entries", "stream=nb_read_frames", "-of", "default=nokey=1:noprint_wrappers=1", input])
-                                    .output().unwrap();
-            frames_amount = String::from_utf8(second_ffprobe_res.stdout).unwrap().replace("\n", "").parse::<u64>().unwrap();
+    // otherwise we have to find out how large the pixels are by trying them all
+    for fiducials in [false, true] {
+        let mut layout = FrameLayout { width, height, colors: 2, palette: PaletteFamily::Color, pixel_size: 1, interleaved: false, fiducials };
+        for pixel_size in 1..=MAX_PIXEL_SIZE {
+            layout.pixel_size = pixel_size;
+            if layout.cells() < METADATA_BLOCK_SIZE * 8 {
+                break;
+            }
+            let raw_frames: Vec<RawFrame> = frames.iter().map(|f| read_raw_frame(f, layout, None)).collect();
+            match correct_metadata(&raw_frames, |m| m.pixel_size == pixel_size && m.fiducials == fiducials) {
+                Ok(Some((metadata, version, voted))) => return Ok(Some(FoundMetadata { metadata, version, voted, geometry: None, layout })),
+                Ok(None) => (),
+                Err(e) => {
+                    error.get_or_insert(e);
+                },
+            }
         }
     }
-    println!("→ Counted {} frames", frames_amount);
+    match error {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
+
+/// a video, or a part of one, and the metadata found in it
+struct FoundPart {
+    input: String,
+    width: usize,
+    height: usize,
+    found: FoundMetadata,
+}
+
+impl FoundPart {
+    /// where the part belongs, counting from 0, a video that didn't get split is its own first part
+    fn index(&self) -> u16 {
+        self.found.metadata.part.map(|p| p.index).unwrap_or(0)
+    }
+
+    /// the data frames of the whole video, which has (data_frames) of them, that are in this part
+    fn data_frames(&self, data_frames: u64) -> Range<u64> {
+        match self.found.metadata.part {
+            Some(p) => p.first_frame..p.first_frame.saturating_add(p.data_frames),
+            None => 0..data_frames,
+        }
+    }
+
+    /// the layout of the data frames, frames with fiducials get resampled to one pixel per cell before they're read
+    fn layout(&self, metadata: &Metadata) -> FrameLayout {
+        match self.found.geometry {
+            Some(v) => FrameLayout { width: v.columns, height: v.rows, pixel_size: 1, ..metadata.layout() },
+            None => FrameLayout { width: self.width, height: self.height, ..metadata.layout() },
+        }
+    }
+}
+
+/// reads the metadata frame of the video (input)
+fn find_part(input: &str) -> Result<FoundPart> {
+    let (width, height) = ffmpeg::probe_dimensions(input)?;
+    let mut ffmpeg = FfmpegReader::spawn(input)?;
+    let frame_size = width * height * 3;
 
     // decode the metadata frame
-    let metadata_path = &get_frames(input, 0, 1)[0];
-    let metadata_frame = read_raw_frame(metadata_path.to_str().unwrap(), 2);     // reminder: the metadata frame *always* has 2 colors
-    let metadata_ecc_decoder = Decoder::new(32);
-
-    let metadata_bytes = &metadata_frame.0[0..250];
-    let metadata_ecc = metadata_ecc_decoder.correct(metadata_bytes, None).unwrap();
-    let metadata = metadata_ecc.data();
-    let encoding_version = u16::from_be_bytes(metadata[0..=1].try_into().unwrap());
-    let colors = u16::from_be_bytes(metadata[2..=3].try_into().unwrap());
-    let pixel_size = metadata[4];
-    let file_size = u64::from_be_bytes(metadata[5..=12].try_into().unwrap());
-    let crc32_checksum = u32::from_be_bytes(metadata[13..=16].try_into().unwrap());
-    let ecc_bytes = metadata[17];
-    let file_name = String::from_utf8(metadata[18..=217].to_vec()).unwrap().replace("\0", "");
-    let width = metadata_frame.3;
-    let height = metadata_frame.4;
-    let content_bytes_per_block: usize = (BLOCK_SIZE - ecc_bytes) as usize;
-    let blocks_per_frame = (((width * height) as f32) / (256f32).log(colors as f32) / (BLOCK_SIZE as f32)) as usize;
-
-    delete_frames(1);
-
-    println!("→ Successfully read metadata frame; 1/{} ({:.1} %)", frames_amount, (100.0f32/frames_amount as f32));
-    println!("→ The file has the following properties:");
-    println!("  • Name: {}", file_name);
-    println!("  • Size: {} Bytes", file_size);
-    println!("  • CRC32: {}", crc32_checksum);
-    println!("  • Encoding version: {}", encoding_version);
-
-    // we're not compatible with files that were encoded with a different version
-    if encoding_version < ENCODING_VERSION {
-        panic!("Encoding version of {} is not compatible with this videobackup version's encoding version ({}). Obtain an earlier version of videobackup and try again.", input, ENCODING_VERSION);
-    }
-    if encoding_version > ENCODING_VERSION {
-        panic!("Encoding version of {} is not compatible with this videobackup version's encoding version ({}). Obtain a newer version of videobackup and try again.", input, ENCODING_VERSION);
-    }
-
-    // prepare multithreading fun by generating the arguments for frame extracting
-    // since it's likely that the number of frames (without metadata) is not cleanly divisible by (threads),
-    // we have to watch out for not trying to read non-existing frames at the end
-    let mut arguments: Vec<(u64, u64)> = Vec::new();
-    let full_runs: u64 = (frames_amount-1) / threads as u64; // -1 because we don't want to add the metadata frame to the actual file
-    let last_run: u64 = (frames_amount-1) % threads as u64;
-    for i in 0..full_runs {
-        arguments.push((i as u64 * threads as u64 + 1, threads as u64));
-    }
-    if last_run != 0 {
-        arguments.push((frames_amount - last_run, last_run))
-    }
-
-    let mut frame_counter: u64 = 0;
-
-    let mut file = fs::File::create(Path::new(&file_name)).unwrap();
-
-    // multithreading fun!
-    for i in arguments {
-        let frames = get_frames(input, i.0, i.1);
-
-        let mut thread_handles = Vec::with_capacity(threads);
-        let mut buf: Vec<(u64, (Vec<u8>, u128, u128, u64, u64))> = Vec::new();
-        
-        let mut current_frame_counter: u64 = 0;
-        for f in frames {
-            let handle = std::thread::spawn(move || {
-                return read_frame(f.to_str().unwrap(), colors, ecc_bytes, blocks_per_frame, frame_counter);
-            });
-            thread_handles.push((current_frame_counter, handle));
-            current_frame_counter += 1;
-            frame_counter += 1;
-        }
-        for t in thread_handles {
-            buf.push((t.0, t.1.join().unwrap()));
-        }
-
-        // threads probably won't finish in order, so let's sort them
-        buf.sort_by(|a, b| a.0.cmp(&b.0));
-
-        for b in buf {
-            file.write(&b.1.0).unwrap();
-            correct_pixels += b.1.1;
-            estimated_pixels += b.1.2;
-            ecced_bytes += b.1.3;
-            unrecoverable_blocks += b.1.4;
-        }
-
-        println!("→ Decoded frames to {}/{} ({:.1} %)", frame_counter + 1, frames_amount, ((((frame_counter + 1) as f32 ) * 100.0f32)/frames_amount as f32));
-
-        delete_frames(i.1);
-    }
-
-    // the last frame probably contains a bunch of useless NULs
-    println!("→ Truncating trailing NULs...");
-    file.set_len(file_size).unwrap();
-
-    if checksum {
-        println!("→ Checking CRC32...");
-        let crc32_end = crc32_file(&file_name);
-        if crc32_checksum == crc32_end {
-            println!("→ CRC32 check successful!")
-        } else {
-            println!("⚠ CRC32 check unsuccessful! Your file is likely corrupted!")
+    let mut metadata_raw = zero_vec(frame_size);
+    if !ffmpeg.read_frame(&mut metadata_raw)? {
+        return Err(VideobackupError::CorruptMetadata(format!("{} doesn't contain any frames", input)));
+    }
+    // the metadata frame *always* has 2 colors, if it has fiducials they tell us where its cells are, otherwise we
+    // have to find out how large its pixels are by trying them all
+    if width * height < METADATA_BLOCK_SIZE * 8 {
+        return Err(VideobackupError::CorruptMetadata(String::from("the metadata frame is too small")));
+    }
+    // metadata that can be corrected but not read might still be readable in the copy at the end of the video
+    let mut found = find_metadata(&[&metadata_raw], width, height);
+    if !matches!(found, Ok(Some(_))) {
+        if let Ok(None) = found {
+            println!("⚠ The metadata frame of {} is damaged, trying the copy at the end of the video", input);
+        }
+        if let Some(last_frame) = ffmpeg::read_last_frame(input, frame_size)? {
+            found = find_metadata(&[&metadata_raw, &last_frame], width, height);
+        }
+    }
+    let found = match found? {
+        Some(v) => v,
+        None => return Err(VideobackupError::CorruptMetadata(String::from("too many errors to correct"))),
+    };
+    if found.voted {
+        println!("⚠ Every copy of the metadata of {} was damaged, it got put back together by voting on every byte", input);
+    }
+    Ok(FoundPart { input: input.to_string(), width, height, found })
+}
+
+/// whether the metadata (a) and (b) are from parts of the same video
+fn same_video(a: &Metadata, b: &Metadata) -> bool {
+    Metadata { part: None, ..a.clone() } == Metadata { part: None, ..b.clone() }
+}
+
+/// what the workers need to know about the part a frame comes from
+struct PartContext {
+    geometry: Option<Geometry>,
+    layout: FrameLayout,
+    calibration: Option<Calibration>,
+}
+
+/// hands the frames of (part) to the workers through (frame_sender), numbering them on from (frame_counter). The
+/// metadata frames get skipped and the calibration frame tells us how the palette of the part looks
+fn send_part(part: &FoundPart, metadata: &Metadata, cluster_palette: bool,
+             frame_sender: &mpsc::SyncSender<(u64, Vec<u8>, Arc<PartContext>)>, frame_counter: &mut u64) -> Result<()> {
+    let (width, height) = (part.width, part.height);
+    let frame_size = width * height * 3;
+    let geometry = part.found.geometry;
+    let layout = part.layout(metadata);
+    if part.found.metadata.part.is_some() {
+        println!("→ Decoding part {} from {}", part.index() as u64 + 1, part.input);
+    }
+    if let Some(geometry) = geometry {
+        if geometry.is_scaled(metadata.pixel_size) || (metadata.width as usize, metadata.height as usize) != (width, height) {
+            println!("ℹ The video was encoded at {}x{} and arrived at {}x{}, the cells get read from where the fiducials say they are",
+                     metadata.width, metadata.height, width, height);
+        }
+    }
+
+    // the metadata frame already got read when the part was found
+    let mut ffmpeg = FfmpegReader::spawn(&part.input)?;
+    if !ffmpeg.read_frame(&mut zero_vec(frame_size))? {
+        return Ok(());
+    }
+
+    // learn how the palette looks after the video got re-encoded, if the calibration frame doesn't look like one
+    // it got lost and we're looking at a data frame that has to be decoded like all the others
+    let mut calibration: Option<Calibration> = None;
+    let mut first_frame: Option<Vec<u8>> = None;
+    if metadata.calibration {
+        let mut frame = zero_vec(frame_size);
+        if ffmpeg.read_frame(&mut frame)? {
+            let resampled = geometry.map(|v| v.resample(&frame));
+            let cells = resampled.as_ref().unwrap_or(&frame);
+            calibration = Calibration::learn(layout, |cell| read_cell(cells, layout, cell));
+            match &calibration {
+                Some(v) => println!("→ Learned the palette from the calibration frame, the colors are off by {:.1} on average",
+                                    v.drift(&Palette::new(metadata.colors, metadata.palette))),
+                None => {
+                    println!("⚠ The calibration frame is missing or damaged, the colors will be compared to the ideal ones");
+                    if cluster_palette {
+                        println!("→ Learning the palette of every frame with k-means");
+                    }
+                    first_frame = Some(frame);
+                }
+            }
+        }
+    }
+    let context = Arc::new(PartContext { geometry, layout, calibration });
+
+    // the copy of the metadata frame at the end must not end up with the data frames
+    let is_metadata_copy = |frame: &[u8]| -> bool {
+        let raw = match geometry {
+            Some(v) => read_raw_frame(&v.resample(frame), part.found.layout, None),
+            None => read_raw_frame(frame, part.found.layout, None),
+        };
+        matches!(correct_metadata(&[raw], |m| *m == part.found.metadata), Ok(Some(_)))
+    };
+
+    // every frame waits until we know it isn't the last one, which might be the copy of the metadata frame
+    let mut held: Option<Vec<u8>> = None;
+    loop {
+        let frame = match first_frame.take() {
+            Some(v) => v,
+            None => {
+                let mut frame = zero_vec(frame_size);
+                if !ffmpeg.read_frame(&mut frame)? {
+                    break;
+                }
+                frame
+            }
+        };
+        if let Some(previous) = held.replace(frame) {
+            // the workers only stop early if something went wrong, the writer tells us what
+            if frame_sender.send((*frame_counter, previous, Arc::clone(&context))).is_err() {
+                return Ok(());
+            }
+            *frame_counter += 1;
         }
     }
+    if let Some(last) = held {
+        if !(metadata.trailing_copy && is_metadata_copy(&last)) && frame_sender.send((*frame_counter, last, context)).is_ok() {
+            *frame_counter += 1;
+        }
+    }
+    Ok(())
+}
+
+impl Decoder {
+    pub fn new(options: DecodeOptions) -> Self {
+        Decoder { options }
+    }
+
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    /// Decodes a video made by the encoder, returns the absolute path of the restored file.
+    pub fn decode<P: AsRef<Path>>(&self, input: P) -> Result<PathBuf> {
+        self.decode_parts(&[input])
+    }
+
+    /// Decodes a video that got split into parts, they can be given in any order. Returns the absolute path of the
+    /// restored file.
+    pub fn decode_parts<P: AsRef<Path>>(&self, inputs: &[P]) -> Result<PathBuf> {
+        let threads = self.options.threads;
+
+        let start_time = Instant::now();
+
+        if threads == 0 {
+            return Err(VideobackupError::InvalidParameters(String::from("Threads have to be at least 1!")));
+        }
+        if inputs.is_empty() {
+            return Err(VideobackupError::InvalidParameters(String::from("There's no video to decode!")));
+        }
+
+        // we want to have some metrics for the end
+        let mut correct_pixels: u128 = 0;
+        let mut estimated_pixels: u128 = 0;
+        let mut ecced_bytes: u64 = 0;
+        let mut unrecoverable_blocks: u64 = 0;
 
-    println!("→ Cleaning up...");
-    std::fs::remove_dir_all(Path::new("tmp")).unwrap();
+        println!("→ Starting videobackup-rs decoder");
 
-    println!("✓ Done in {} seconds!", (start_time.elapsed().as_millis() as f32 / 1000.0f32));
-    let guessed_percentage: f32 = (estimated_pixels as f32 * 100.0f32) / (estimated_pixels + correct_pixels) as f32;
-    let ecced_percentage: f32 = (ecced_bytes as f32 * 100.0f32) / file_size as f32;
-    println!("  • Total pixels: {} - Guessed pixels: {} - Perfectly read pixels: {} - Percentage of guessed pixels: {:.1} %", estimated_pixels + correct_pixels, estimated_pixels, correct_pixels, guessed_percentage);
-    println!("  • Total bytes: {} - Unrecoverable bytes: {} - ECC'ed bytes: {} - Perfectly read bytes: {} - Percentage of ECC'ed bytes: {:.1} %", file_size, unrecoverable_blocks as usize * content_bytes_per_block, ecced_bytes, file_size - ecced_bytes, ecced_percentage);
-}
\ No newline at end of file
+        // the metadata frame of every part says where the part belongs, so they can come in any order
+        let mut parts: Vec<FoundPart> = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let part = find_part(&input.as_ref().to_string_lossy())?;
+            if let Some(first) = parts.first() {
+                if !same_video(&first.found.metadata, &part.found.metadata) {
+                    return Err(VideobackupError::InvalidParameters(format!("{} belongs to a different video than {}", part.input, first.input)));
+                }
+            }
+            if parts.iter().any(|p| p.index() == part.index()) {
+                println!("⚠ {} is part {} again, it gets skipped", part.input, part.index() as u64 + 1);
+                continue;
+            }
+            parts.push(part);
+        }
+        parts.sort_by_key(|p| p.index());
+        let found = &parts[0].found;
+
+        // the fields of an encrypted video that give anything away about the file are sealed with the file key, which
+        // the passphrase or one of the identities unlocks
+        let mut metadata = Metadata { part: None, ..found.metadata.clone() };
+        let mut file_key: Option<crypto::Key> = None;
+        if let Some(encryption) = &found.metadata.encryption {
+            println!("→ The video is encrypted");
+            let key = crypto::unlock(&encryption.recipients, self.options.passphrase.as_deref(), &self.options.identities)?;
+            metadata.unseal(&key)?;
+            file_key = Some(key);
+        }
+        let metadata = &metadata;
+        let Metadata { file_size, crc32: crc32_checksum, ecc_bytes, colors, palette, pixel_size, archive_id, .. } = *metadata;
+        let file_name = &metadata.file_name;
+        let has_calibration = metadata.calibration;
+        let trailing_metadata = metadata.trailing_copy;
+        let parity_group = metadata.parity_group as u64;
+        let parity_frames = metadata.parity_frames as u64;
+        let content_bytes_per_block: usize = (BLOCK_SIZE - ecc_bytes) as usize;
+        let frame_header_size = if archive_id.is_some() { FRAME_HEADER_SIZE } else { 0 };
+        let blocks_per_frame = parts[0].layout(metadata).blocks_per_frame(frame_header_size);
+        if blocks_per_frame == 0 {
+            return Err(VideobackupError::CorruptMetadata(format!("not a single block fits into a {}x{} frame", parts[0].width, parts[0].height)));
+        }
+        // a part that arrived at another size still has to hold as many blocks per frame as the others
+        if let Some(part) = parts.iter().find(|p| p.layout(metadata).blocks_per_frame(frame_header_size) != blocks_per_frame) {
+            return Err(VideobackupError::CorruptMetadata(format!("{} fits a different number of blocks into a frame than the other parts", part.input)));
+        }
+
+        let content_bytes_per_frame = blocks_per_frame * content_bytes_per_block;
+        let data_frames = file_size.div_ceil(content_bytes_per_frame as u64);
+        // every encrypted chunk fills exactly one frame
+        let chunk_size = content_bytes_per_frame.saturating_sub(AEAD_OVERHEAD);
+        if metadata.encryption.as_ref().map(|e| e.chunk_size as usize != chunk_size).unwrap_or(false) {
+            return Err(VideobackupError::CorruptMetadata(String::from("the encrypted chunks don't fit the frames")));
+        }
+        if parts.iter().any(|p| p.data_frames(data_frames).end > data_frames) {
+            return Err(VideobackupError::CorruptMetadata(String::from("a part has more frames than the whole video")));
+        }
+        // the metadata frame and the calibration frame come before all the others, in every part
+        let leading_frames: u64 = if has_calibration { 2 } else { 1 };
+        let needed_frames: u64 = parts.iter().map(|p| {
+            let frames = p.data_frames(data_frames);
+            let frames = frames.end - frames.start;
+            let parity = if parity_frames > 0 { frames.div_ceil(parity_group) * parity_frames } else { 0 };
+            frames + parity + leading_frames + if trailing_metadata { 1 } else { 0 }
+        }).sum();
+
+        println!("→ Successfully read metadata frame; 1/{} ({:.1} %)", needed_frames, (100.0f32/needed_frames as f32));
+        println!("→ The file has the following properties:");
+        println!("  • Name: {}", file_name);
+        if metadata.directory {
+            println!("  • Type: directory");
+        }
+        // the size of the data before it got encrypted
+        let plain_size = if file_key.is_some() { crypto::decrypted_size(file_size, chunk_size) } else { file_size };
+        match metadata.compression {
+            Some((compression, size)) => {
+                println!("  • Size: {} Bytes", size);
+                println!("  • Compression: {}, {} Bytes compressed", compression, plain_size);
+            },
+            None => println!("  • Size: {} Bytes", plain_size),
+        }
+        println!("  • CRC32: {}", crc32_checksum);
+        if let Some((algorithm, hash)) = &metadata.hash {
+            println!("  • {}: {}", algorithm, to_hex(hash));
+        }
+        println!("  • Encoding version: {}", found.version);
+        println!("  • Pixel size: {}", pixel_size);
+        println!("  • Palette: {} colors, {}", colors, palette);
+        if let Some(archive_id) = archive_id {
+            println!("  • Archive ID: {}", to_hex(&archive_id));
+        }
+        if let Some(encryption) = &metadata.encryption {
+            println!("  • Encryption: {}, {} Bytes in the video", crypto::describe_recipients(&encryption.recipients), file_size);
+        }
+        if let Some(modified) = metadata.modified {
+            println!("  • Modified: {}", format_timestamp(modified));
+        }
+        if let Some(created) = metadata.created {
+            println!("  • Encoded: {}", format_timestamp(created));
+        }
+        if let Some(comment) = &metadata.comment {
+            println!("  • Comment: {}", comment);
+        }
+        if found.metadata.part.is_some() {
+            println!("  • Parts: {}", parts.iter().map(|p| (p.index() as u64 + 1).to_string()).collect::<Vec<String>>().join(", "));
+            // every part starts where the one before it ended, so the gaps between them are the missing parts
+            let mut missing: Vec<u64> = Vec::new();
+            let mut next = 0;
+            for part in parts.iter() {
+                missing.extend((next..part.index() as u64).map(|i| i + 1));
+                next = part.index() as u64 + 1;
+            }
+            if !missing.is_empty() {
+                println!("⚠ Missing parts: {}", format_ranges(&missing));
+            }
+            let last = parts.last().unwrap();
+            if last.data_frames(data_frames).end < data_frames {
+                println!("⚠ The parts after part {} are missing", last.index() as u64 + 1);
+            }
+        }
+        if self.options.extract.is_some() && !metadata.directory {
+            return Err(VideobackupError::InvalidParameters(String::from("The video holds a single file, there's nothing to extract from it")));
+        }
+        if self.options.cluster_palette && !has_calibration {
+            println!("→ Learning the palette of every frame with k-means");
+        }
+        let cluster_palette = self.options.cluster_palette;
+
+        let output_path = self.options.output_dir.join(file_name);
+        // a directory gets decoded into an archive next to where it ends up, and unpacked from there
+        let archive_file = if metadata.directory { Some(TempFile::next_to(&output_path, "archive")) } else { None };
+        let decoded_path = archive_file.as_ref().map(|t| t.path.clone()).unwrap_or_else(|| output_path.clone());
+        // an encrypted or compressed file gets decoded into a temporary file first, then it gets decrypted and
+        // decompressed from there at the end
+        let compressed_file = metadata.compression.map(|_| TempFile::next_to(&output_path, "compressed"));
+        let compressed_path = compressed_file.as_ref().map(|t| t.path.clone()).unwrap_or_else(|| decoded_path.clone());
+        let encrypted_file = file_key.map(|_| TempFile::next_to(&output_path, "encrypted"));
+        let data_path = encrypted_file.as_ref().map(|t| t.path.clone()).unwrap_or_else(|| compressed_path.clone());
+        // the file gets opened for reading too, reconstructing frames from parity frames needs the intact ones
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&data_path)?;
+        file.set_len(file_size)?;
+
+        // multithreading fun! the frames are handed to the workers through a bounded channel so we never hold
+        // more than a few frames in memory, the workers send the read frames to a writer thread which writes each of them
+        // to where it belongs in the file, so frames can come in any order and duplicates get skipped. Every frame
+        // comes with what the workers need to know about the part it's from
+        let (frame_sender, frame_receiver) = mpsc::sync_channel::<(u64, Vec<u8>, Arc<PartContext>)>(threads * 2);
+        let frame_receiver = Arc::new(Mutex::new(frame_receiver));
+        let (result_sender, result_receiver) = mpsc::channel::<ReadFrame>();
+
+        let mut workers = Vec::with_capacity(threads);
+        for _ in 0..threads {
+            let frame_receiver = Arc::clone(&frame_receiver);
+            let result_sender = result_sender.clone();
+            workers.push(thread::spawn(move || {
+                loop {
+                    // only hold the lock while receiving so the other workers can get frames too
+                    let next = frame_receiver.lock().unwrap().recv();
+                    let (number, frame, context) = match next {
+                        Ok(v) => v,
+                        Err(_) => break,
+                    };
+                    let frame = match context.geometry {
+                        Some(v) => v.resample(&frame),
+                        None => frame,
+                    };
+                    let source = match context.calibration.as_ref() {
+                        Some(v) => PaletteSource::Calibrated(v),
+                        None if cluster_palette => PaletteSource::Clustered,
+                        None => PaletteSource::Ideal,
+                    };
+                    if result_sender.send(read_frame(&frame, context.layout, source, ecc_bytes, blocks_per_frame, archive_id, number)).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        // only the workers may hold on to the receiver, otherwise we'd wait forever for them if they all died
+        drop(frame_receiver);
+        drop(result_sender);
+
+        let mut frame_writer = FrameWriter {
+            file,
+            states: FrameStates { states: vec![FrameState::Missing; data_frames as usize], unreadable: 0, foreign: 0, duplicates: 0 },
+            file_size,
+            content_bytes_per_frame,
+            data_frames,
+            parity_group,
+            parity_frames,
+            parity: HashMap::new(),
+            correct_pixels: 0,
+            estimated_pixels: 0,
+            ecced_bytes: 0,
+            unrecoverable_blocks: 0,
+            palette_drift: (0.0, 0.0),
+            clustered_frames: 0,
+        };
+        let leading_frames_total = leading_frames * parts.len() as u64;
+        let writer = thread::spawn(move || -> Result<FrameWriter> {
+            let mut processed_frames: u64 = 0;
+            for read in result_receiver {
+                processed_frames += 1;
+                if processed_frames.is_multiple_of(threads as u64) {
+                    println!("→ Decoded frames to {}/{} ({:.1} %)", processed_frames + leading_frames_total, needed_frames, ((((processed_frames + leading_frames_total) as f32 ) * 100.0f32)/needed_frames as f32));
+                }
+                frame_writer.write(read)?;
+            }
+            Ok(frame_writer)
+        });
+
+        let mut frame_counter: u64 = 0;
+        let mut read_result = Ok(());
+        for part in parts.iter() {
+            if let Err(e) = send_part(part, metadata, cluster_palette, &frame_sender, &mut frame_counter) {
+                read_result = Err(e);
+                break;
+            }
+        }
+
+        // closing the channel lets the workers and then the writer finish
+        drop(frame_sender);
+        for w in workers {
+            w.join().unwrap();
+        }
+        let mut frame_writer = writer.join().unwrap()?;
+        read_result?;
+        correct_pixels += frame_writer.correct_pixels;
+        estimated_pixels += frame_writer.estimated_pixels;
+        ecced_bytes += frame_writer.ecced_bytes;
+        unrecoverable_blocks += frame_writer.unrecoverable_blocks;
+
+        println!("→ Decoded {} data frames", frame_counter);
+        if parity_frames > 0 {
+            let recovered = frame_writer.recover()?;
+            if recovered > 0 {
+                println!("→ Recovered {} frames using parity frames", recovered);
+            }
+        }
+        frame_writer.states.report();
+        if frame_writer.clustered_frames > 0 {
+            println!("→ The palette learned with k-means drifted by {:.1} on average and by up to {:.1} in a single frame",
+                     frame_writer.palette_drift.0 / frame_writer.clustered_frames as f64, frame_writer.palette_drift.1);
+        }
+
+        let mut file = frame_writer.file;
+        file.flush()?;
+        file.set_len(file_size)?;
+        if let Some(key) = &file_key {
+            println!("→ Decrypting the file...");
+            let damaged = crypto::decrypt_file(key, chunk_size, &data_path, &compressed_path)?;
+            if damaged > 0 {
+                println!("⚠ {} chunks of the file were damaged beyond repair and couldn't be decrypted, they're filled with zeros", damaged);
+            }
+            file = fs::OpenOptions::new().write(true).open(&compressed_path)?;
+        }
+        if let Some((compression, size)) = metadata.compression {
+            println!("→ Decompressing the file with {}...", compression);
+            let decompressed = decompress_file(compression, &compressed_path, &decoded_path)?;
+            if decompressed != size {
+                println!("⚠ The decompressed file has {} Bytes instead of {}", decompressed, size);
+            }
+            file = fs::OpenOptions::new().write(true).open(&decoded_path)?;
+        }
+        if let (Some(modified), false) = (metadata.modified, metadata.directory) {
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(modified))?;
+        }
+
+        println!("✓ Done in {} seconds!", (start_time.elapsed().as_millis() as f32 / 1000.0f32));
+        let guessed_percentage: f32 = (estimated_pixels as f32 * 100.0f32) / (estimated_pixels + correct_pixels) as f32;
+        let ecced_percentage: f32 = (ecced_bytes as f32 * 100.0f32) / file_size as f32;
+        println!("  • Total pixels: {} - Guessed pixels: {} - Perfectly read pixels: {} - Percentage of guessed pixels: {:.1} %", estimated_pixels + correct_pixels, estimated_pixels, correct_pixels, guessed_percentage);
+        println!("  • Total bytes: {} - Unrecoverable bytes: {} - ECC'ed bytes: {} - Perfectly read bytes: {} - Percentage of ECC'ed bytes: {:.1} %", file_size, unrecoverable_blocks as usize * content_bytes_per_block, ecced_bytes, file_size.saturating_sub(ecced_bytes), ecced_percentage);
+
+        if self.options.checksum {
+            println!("→ Checking CRC32...");
+            let crc32_end = crc32_file(&decoded_path)?;
+            if crc32_checksum != crc32_end {
+                return Err(VideobackupError::ChecksumMismatch { expected: crc32_checksum, actual: crc32_end });
+            }
+            println!("→ CRC32 check successful!");
+            if let Some((algorithm, hash)) = &metadata.hash {
+                println!("→ Checking {}...", algorithm);
+                let hash_end = hash_file(*algorithm, &decoded_path)?;
+                if *hash != hash_end {
+                    return Err(VideobackupError::HashMismatch { algorithm: *algorithm, expected: to_hex(hash), actual: to_hex(&hash_end) });
+                }
+                println!("→ {} check successful!", algorithm);
+            }
+        }
+
+        if metadata.directory {
+            let member = self.options.extract.as_deref();
+            let (files, directories) = archive::extract(&decoded_path, &output_path, member)?;
+            println!("→ Unpacked {} files and {} directories into {}", files, directories, output_path.display());
+            if let Some(member) = member {
+                return Ok(output_path.join(member.trim_matches('/')).absolutize()?.to_path_buf());
+            }
+            // unpacking touched the directory itself, so it only gets its time now
+            if let (Some(modified), Ok(directory)) = (metadata.modified, fs::File::open(&output_path)) {
+                let _ = directory.set_modified(UNIX_EPOCH + Duration::from_secs(modified));
+            }
+        }
+
+        Ok(output_path.absolutize()?.to_path_buf())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ENCODING_VERSION;
+    use reed_solomon::Encoder as EccEncoder;
+
+    /// a block of (data) bytes with (ecc_count) ECC bytes
+    fn block(data: usize, ecc_count: usize) -> Vec<u8> {
+        let bytes: Vec<u8> = (0..data).map(|i| (i * 37 + 11) as u8).collect();
+        EccEncoder::new(ecc_count).encode(&bytes).to_vec()
+    }
+
+    #[test]
+    fn least_confident_bytes_are_erased() {
+        let mut confidence = vec![EXACT; 250];
+        for (i, position) in [3, 40, 41, 100, 249].iter().enumerate() {
+            confidence[*position] = 10 - i as u32;
+        }
+        assert_eq!(erasures(&confidence, 32), [249, 100, 41, 40, 3]);
+        // only half of the ECC bytes go to erasures
+        assert_eq!(erasures(&confidence, 4), [249, 100]);
+        assert!(erasures(&[EXACT; 250], 32).is_empty());
+    }
+
+    #[test]
+    fn erasures_correct_more_errors() {
+        let decoder = EccDecoder::new(32);
+        let original = block(218, 32);
+        // more errors than the ECC can find on its own, the ones read with confidence are in the first bytes
+        let mut damaged = original.clone();
+        let mut confidence = vec![EXACT; damaged.len()];
+        for i in 0..20 {
+            damaged[i * 12] ^= 0xff;
+            if i >= 4 {
+                confidence[i * 12] = 1;
+            }
+        }
+        let erasures = erasures(&confidence, 32);
+        assert_eq!(erasures.len(), 16);
+        assert!(decoder.correct(&damaged, None).is_err());
+        let (corrected, _) = correct_with_erasures(&decoder, &damaged, &erasures).unwrap();
+        assert_eq!(corrected.data(), &original[..218]);
+        assert!(correct_with_erasures(&decoder, &damaged, &[]).is_err());
+    }
+
+    #[test]
+    fn broken_erasures_are_ignored() {
+        let decoder = EccDecoder::new(32);
+        let original = block(100, 32);
+        let mut damaged = original.clone();
+        for i in 0..10 {
+            damaged[i * 13] ^= 0xff;
+        }
+        // erased twice and outside of the block
+        let erasures = [0, 0, 13, 26, 26, 131, 132, 200, 255];
+        let (corrected, _) = correct_with_erasures(&decoder, &damaged, &erasures).unwrap();
+        assert_eq!(corrected.data(), &original[..100]);
+        // erasures that are all wrong still leave the errors to be found without them
+        let mut damaged = original.clone();
+        damaged[50] ^= 0xff;
+        let (corrected, _) = correct_with_erasures(&decoder, &damaged, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17]).unwrap();
+        assert_eq!(corrected.data(), &original[..100]);
+    }
+
+    /// a metadata frame of (frame_bytes) bytes with as many copies of (metadata) as fit into it
+    fn metadata_frame(metadata: &Metadata, frame_bytes: usize) -> RawFrame {
+        let copy = metadata.to_bytes().unwrap();
+        let mut bytes = vec![0; frame_bytes];
+        for offset in metadata_offsets(frame_bytes, copy.len()) {
+            bytes[offset..(offset + copy.len())].copy_from_slice(&copy);
+        }
+        RawFrame { confidence: vec![EXACT; bytes.len()], bytes, correct_pixels: 0, estimated_pixels: 0 }
+    }
+
+    #[test]
+    fn metadata_copies_are_voted_on() {
+        let metadata = Metadata { file_name: String::from("a"), file_size: 1, crc32: 1, ecc_bytes: 32, colors: 2, pixel_size: 1, width: 8, height: 8,
+                                  ..Metadata::empty() };
+        let size = metadata.to_bytes().unwrap().len();
+        let mut frame = metadata_frame(&metadata, 5000);
+        let offsets = metadata_offsets(frame.bytes.len(), size);
+        assert_eq!(offsets.len(), 5);
+        assert_eq!(correct_metadata(std::slice::from_ref(&frame), |_| true).unwrap(), Some((metadata.clone(), ENCODING_VERSION, false)));
+
+        // every copy gets more errors than the ECC can correct, but never in the same bytes as another copy
+        for (c, offset) in offsets.iter().enumerate() {
+            for i in (c..size).step_by(offsets.len()) {
+                frame.bytes[offset + i] ^= 0x55;
+            }
+        }
+        for offset in offsets.iter() {
+            assert!(Metadata::read(&frame.bytes[*offset..(offset + size)], ENCODING_VERSION).is_none());
+        }
+        assert_eq!(correct_metadata(&[frame], |_| true).unwrap(), Some((metadata, ENCODING_VERSION, true)));
+    }
+}
@@ -1,266 +1,839 @@
 extern crate crc32fast;
-extern crate image;
+extern crate num_cpus;
 extern crate path_absolutize;
 extern crate reed_solomon;
 
+use crate::archive::write_archive;
+use crate::calibration::calibration_bytes;
+use crate::codec::{PixelFormat, VideoCodec};
+use crate::compression::{compress_file, Compression};
+use crate::crypto;
+use crate::crypto::{Encryption, X25519Recipient, AEAD_OVERHEAD};
 use crate::common::BLOCK_SIZE;
-use crate::common::ENCODING_VERSION;
+use crate::common::{interleave, metadata_offsets, FrameLayout, MAX_PIXEL_SIZE};
 use crate::common::crc32_file;
+use crate::common::random_bytes;
+use crate::common::TempFile;
 use crate::common::zero_vec;
-
-use image::{ImageBuffer, RgbImage};
+use crate::error::{Result, VideobackupError};
+use crate::ffmpeg::FfmpegWriter;
+use crate::hash::{hash_file, to_hex, HashAlgorithm};
+use crate::geometry::fiducial_white;
+use crate::header::{FrameHeader, FRAME_HEADER_SIZE};
+use crate::metadata::{Metadata, Part, MAX_FILE_NAME_LENGTH, METADATA_BLOCK_SIZE};
+use crate::palette::{Palette, PaletteFamily};
+use crate::parity;
+use crate::parity::MAX_GROUP_FRAMES;
 
 use path_absolutize::Absolutize;
 
-use reed_solomon::Encoder;
+use reed_solomon::Encoder as EccEncoder;
 
-use std::convert::TryInto;
+use std::convert::TryFrom;
 use std::fs;
 use std::io::prelude::*;
-use std::path::Path;
-use std::process::Command;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::thread;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+
+/// Options for encoding a file into a video. Start with `EncodeOptions::new()` and chain the setters.
+#[derive(Clone, Debug)]
+pub struct EncodeOptions {
+    fps: u16,
+    width: usize,
+    height: usize,
+    colors: u16,
+    palette: PaletteFamily,
+    pixel_size: u8,
+    interleave: bool,
+    calibration: bool,
+    fiducials: bool,
+    ecc_bytes: u8,
+    video_codec: VideoCodec,
+    pixel_format: PixelFormat,
+    crf: u16,
+    parity_group: u8,
+    parity_frames: u8,
+    comment: Option<String>,
+    hash: Option<HashAlgorithm>,
+    compression: Option<Compression>,
+    passphrase: Option<String>,
+    recipients: Vec<X25519Recipient>,
+    max_frames: Option<u64>,
+    max_duration: Option<u64>,
+    max_size: Option<u64>,
+    threads: usize,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            fps: 6,
+            width: 3840,
+            height: 2160,
+            colors: 2,
+            palette: PaletteFamily::Color,
+            pixel_size: 1,
+            interleave: true,
+            calibration: true,
+            fiducials: true,
+            ecc_bytes: 16,
+            video_codec: VideoCodec::X264,
+            pixel_format: PixelFormat::Yuv444p,
+            crf: 24,
+            parity_group: 16,
+            parity_frames: 0,
+            comment: None,
+            hash: Some(HashAlgorithm::Sha256),
+            compression: None,
+            passphrase: None,
+            recipients: Vec::new(),
+            max_frames: None,
+            max_duration: None,
+            max_size: None,
+            threads: num_cpus::get(),
+        }
+    }
+}
+
+impl EncodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn layout(&self) -> FrameLayout {
+        FrameLayout { width: self.width, height: self.height, colors: self.colors, palette: self.palette, pixel_size: self.pixel_size, interleaved: self.interleave,
+                      fiducials: self.fiducials }
+    }
+
+    /// FPS for the video, 6 is optimal
+    pub fn fps(mut self, fps: u16) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// width of the video
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// height of the video
+    pub fn height(mut self, height: usize) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// amount of colors used, a power of two from 2 to 256 (8 for grayscale palettes)
+    pub fn colors(mut self, colors: u16) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// which kind of palette to use, grayscale palettes survive the chroma subsampling most platforms do
+    pub fn palette(mut self, palette: PaletteFamily) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// every color gets drawn as a square of (pixel_size)x(pixel_size) pixels, larger squares survive heavier
+    /// compression but less data fits into a frame
+    pub fn pixel_size(mut self, pixel_size: u8) -> Self {
+        self.pixel_size = pixel_size;
+        self
+    }
+
+    /// whether to spread the bytes of every block across the whole frame, so compression artifacts in one spot
+    /// don't use up all the ECC bytes of a single block
+    pub fn interleave(mut self, interleave: bool) -> Self {
+        self.interleave = interleave;
+        self
+    }
+
+    /// whether to add a calibration frame after the metadata frame, the decoder learns from it how the colors of the
+    /// palette look after the video went through whatever platform it was uploaded to
+    pub fn calibration(mut self, calibration: bool) -> Self {
+        self.calibration = calibration;
+        self
+    }
+
+    /// whether to draw fiducials around the cells of every frame, the decoder uses them to find the cells again when
+    /// the video got scaled, letterboxed or slightly cropped
+    pub fn fiducials(mut self, fiducials: bool) -> Self {
+        self.fiducials = fiducials;
+        self
+    }
+
+    /// amount of ECC bytes in a 128-byte block
+    pub fn ecc_bytes(mut self, ecc_bytes: u8) -> Self {
+        self.ecc_bytes = ecc_bytes;
+        self
+    }
+
+    /// which video codec to encode with
+    pub fn video_codec(mut self, video_codec: VideoCodec) -> Self {
+        self.video_codec = video_codec;
+        self
+    }
+
+    /// how the colors of the frames get stored, see PixelFormat for which one to pick
+    pub fn pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
 
+    /// quality of the video (constant rate factor), ignored by lossless codecs
+    pub fn crf(mut self, crf: u16) -> Self {
+        self.crf = crf;
+        self
+    }
+
+    /// adds (parity_frames) parity frames after every (parity_group) data frames, as long as no more than (parity_frames)
+    /// frames of a group get lost or damaged, the decoder can reconstruct them; 0 parity frames turn this off
+    pub fn parity(mut self, parity_group: u8, parity_frames: u8) -> Self {
+        self.parity_group = parity_group;
+        self.parity_frames = parity_frames;
+        self
+    }
+
+    /// a comment that gets stored in the metadata and shown when decoding
+    pub fn comment<S: Into<String>>(mut self, comment: Option<S>) -> Self {
+        self.comment = comment.map(|c| c.into());
+        self
+    }
+
+    /// which cryptographic hash of the file to store in the metadata next to the CRC32, None to only store the CRC32
+    pub fn hash(mut self, hash: Option<HashAlgorithm>) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// which algorithm to compress the file with before it gets encoded, None to leave it as it is
+    pub fn compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// encrypts the file and everything in the metadata that gives away anything about it with (passphrase), None to
+    /// leave it unencrypted
+    pub fn passphrase<S: Into<String>>(mut self, passphrase: Option<S>) -> Self {
+        self.passphrase = passphrase.map(|p| p.into());
+        self
+    }
+
+    /// encrypts the file for the holders of the identities of (recipients) too, if there's also a passphrase either
+    /// of them can decode it; no recipients and no passphrase leave it unencrypted
+    pub fn recipients(mut self, recipients: Vec<X25519Recipient>) -> Self {
+        self.recipients = recipients;
+        self
+    }
+
+    /// splits the video into parts of at most (max_frames) frames each, None for no limit
+    pub fn max_frames(mut self, max_frames: Option<u64>) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    /// splits the video into parts of at most (max_duration) seconds each, None for no limit
+    pub fn max_duration(mut self, max_duration: Option<u64>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
 
-fn build_frame(bytes: &[u8], fps: u16, width: usize, height: usize, colors: u16, count: u32, video_codec: String) -> std::path::PathBuf {
+    /// splits the video into parts of at most (max_size) bytes each, None for no limit. How large a part gets is only
+    /// known once it's encoded, so parts that turn out too large get encoded again with fewer frames
+    pub fn max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// how many threads to use
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+}
+
+/// what encoding the data frames of a part needs to know about them
+struct DataLayout {
+    archive_id: [u8; 16],
+    content_bytes_per_frame: usize,
+    /// how many bytes of the file a data frame holds, that's less than it has room for if the chunks get encrypted
+    chunk_size: usize,
+    /// of the whole video, not just the part
+    data_frames: u64,
+    leading_frames: u64,
+    file_key: Option<crypto::Key>,
+}
+
+/// how many data frames fit into a part with at most (max_frames) frames, with parity frames that's a whole number of
+/// parity groups so every part can be repaired on its own
+fn data_frames_per_part(max_frames: u64, leading_frames: u64, parity_group: u64, parity_frames: u64) -> u64 {
+    let available = max_frames.saturating_sub(leading_frames + 1);
+    if parity_frames > 0 {
+        available / (parity_group + parity_frames) * parity_group
+    } else {
+        available
+    }
+}
+
+/// the sequence number of a frame as its frame header holds it, which only has room for 32 bits
+fn frame_sequence(sequence: u64) -> Result<u32> {
+    u32::try_from(sequence).map_err(|_| VideobackupError::InvalidParameters(format!("The video can't have more than {} data and parity frames, use larger frames or more colors!",
+                                                                                    u32::MAX as u64 + 1)))
+}
+
+/// the path of part (index) of the video (output), backup.mp4 turns into backup.part1.mp4
+fn part_path(output: &Path, index: usize) -> PathBuf {
+    let stem = output.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match output.extension() {
+        Some(extension) => format!("{}.part{}.{}", stem, index + 1, extension.to_string_lossy()),
+        None => format!("{}.part{}", stem, index + 1),
+    };
+    output.with_file_name(name)
+}
+
+/// Turns files into videos according to its `EncodeOptions`.
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    options: EncodeOptions,
+}
+
+/// renders (bytes) into a raw RGB24 frame that can be piped into ffmpeg
+pub(crate) fn build_frame(bytes: &[u8], layout: FrameLayout) -> Result<Vec<u8>> {
     // check whether someone supplied to many bytes for our image
-    if bytes.len() as f32 > ((width * height) as f32) / (colors as f32).log(256.0) {
-        panic!("Byte array is too large for the image size!")
-    }
-
-    // declare our color_palettes
-    let two_color_palette = [image::Rgb([0, 0, 0]), image::Rgb([255, 255, 255])]; 
-    let four_color_palette = [image::Rgb([0, 0, 0]), image::Rgb([255, 0, 0]), image::Rgb([0, 255, 0]), image::Rgb([0, 0, 255])];
-
-    // construct a new image based on our width and height
-    let mut image: RgbImage = ImageBuffer::new((width as usize).try_into().unwrap(), (height as usize).try_into().unwrap());
-    
-    // enumerate over byte array
-    for i in 0..bytes.len() {
-        if colors == 2 {
-            // go through every bit of the current byte
-            for j in 0..8 {
-                // TODO: Optimize all those type conversions away
-                // get the current pixel's position
-                let pixel_x: u32 = ((i * 8 + j) % width).try_into().unwrap();
-                let pixel_y: u32 = ((i * 8 + j) / width).try_into().unwrap();
-                // get the j'th bit in the current byte
-                let bit: u8 = ((bytes[i] & ((128 / (u8::pow(2, j.try_into().unwrap()))) as u8)) >> (7-j)).into();
-                // paint the "calculated" color to the image
-                let pixel = two_color_palette[bit as usize];
-                image.put_pixel(pixel_x, pixel_y, pixel);
+    if bytes.len() > layout.bytes() {
+        return Err(VideobackupError::InvalidParameters(String::from("Byte array is too large for the image size!")));
+    }
+
+    let palette = Palette::new(layout.colors, layout.palette);
+    let bits = palette.bits();
+
+    // construct a new, black frame based on our width and height
+    let mut frame: Vec<u8> = zero_vec(layout.width * layout.height * 3);
+    let pixel_size = layout.pixel_size as usize;
+
+    // paints the cell whose top left pixel is at the given position in the given color
+    let mut paint = |(x, y): (usize, usize), color: &[u8; 3]| {
+        for row in y..(y + pixel_size) {
+            for column in x..(x + pixel_size) {
+                let pixel = (row * layout.width + column) * 3;
+                frame[pixel..pixel + 3].copy_from_slice(color);
             }
-        } else {
-            for j in 0..4 {
-                // TODO: Optimize all those type conversions away
-                // get the current pixel's position
-                let pixel_x: u32 = ((i * 4 + j) % width).try_into().unwrap();
-                let pixel_y: u32 = ((i * 4 + j) / width).try_into().unwrap();
-                // get the j'th bit pair in the current byte
-                let and_mask: u8 = 0b11000000 >> j*2;
-                let bit_pair: u8 = (bytes[i] & and_mask) >> (3-j)*2;
-                // paint the "calculated" color to the image
-                let pixel = four_color_palette[bit_pair as usize];
-                image.put_pixel(pixel_x, pixel_y, pixel);
+        }
+    };
+
+    if layout.fiducials {
+        let (columns, rows) = (layout.grid_columns(), layout.grid_rows());
+        for y in 0..rows {
+            for x in 0..columns {
+                if fiducial_white(x, y, columns, rows) {
+                    paint((x * pixel_size, y * pixel_size), &[255, 255, 255]);
+                }
             }
         }
     }
 
-    // we want to save the image in a tmp folder
-    let img_path = Path::new("tmp").join(format!("{}.png", count)).absolutize().unwrap().to_str().unwrap().to_string();
-    match image.save(&img_path) {
-        Err(e) => println!("Error saving file #{}: {:?}", count, e),
-        Ok(v) => (),
+    // the bytes are one long stream of bits, every cell takes the next (bits) bits of it, starting with the most
+    // significant bit of the first byte
+    let mut cell = 0;
+    let mut pending: u32 = 0;
+    let mut pending_bits: u32 = 0;
+    for byte in bytes.iter() {
+        pending = (pending << 8) | *byte as u32;
+        pending_bits += 8;
+        while pending_bits >= bits {
+            pending_bits -= bits;
+            paint(layout.cell_position(cell), &palette.color(((pending >> pending_bits) & ((1 << bits) - 1)) as usize));
+            cell += 1;
+        }
+        pending &= (1 << pending_bits) - 1;
+    }
+    // the last cell might not get all of its bits
+    if pending_bits > 0 {
+        paint(layout.cell_position(cell), &palette.color((pending << (bits - pending_bits)) as usize));
     }
 
-    // convert it to an MPEG-TS using ffmpeg for easy stitching later
-    let ts_path = Path::new("tmp").join(format!("{}.ts", count));
-    Command::new("ffmpeg")
-            .args(&["-y", "-r", &fps.to_string(), "-i", &img_path, "-t", &(1.0f32/(fps as f32)).to_string(), "-c:v", &video_codec,
-            "-bsf:v", "h264_mp4toannexb", "-f", "mpegts", ts_path.to_str().unwrap()])
-            .output().unwrap();
-
-    return ts_path;
+    Ok(frame)
 }
 
-/// This function takes a vector of byte arrays (the data part of the blocks), appends the ECC and then calls build_frame
-fn prepare_build_image(bytes: Vec<Vec<u8>>, fps: u16, width: usize, height: usize, colors: u16, ecc_bytes: u8, count: u32, video_codec: String) -> std::path::PathBuf {
+/// This function takes the content bytes of a frame, puts the frame header in front of them, cuts them into blocks,
+/// appends the ECC to each block and then calls build_frame
+fn prepare_build_image(content: &[u8], mut header: FrameHeader, layout: FrameLayout, ecc_bytes: u8) -> Result<Vec<u8>> {
     // the names were just too long
-    let ecc_encoder = Encoder::new(ecc_bytes as usize);
+    let ecc_encoder = EccEncoder::new(ecc_bytes as usize);
+    let content_bytes_per_block = BLOCK_SIZE as usize - ecc_bytes as usize;
+    // the header carries the CRC32 of everything that comes after it
+    header.crc32 = crc32fast::hash(content);
     // initialize a vector with allocated space of blocks_per_frame * block_size, so basically the amount of bytes to be processed
-    let mut bytes_for_frame: Vec<u8> = Vec::with_capacity(bytes.len() * BLOCK_SIZE as usize);
-    for i in bytes.iter() {
+    let mut bytes_for_frame: Vec<u8> = Vec::with_capacity(FRAME_HEADER_SIZE + (content.len() / content_bytes_per_block) * BLOCK_SIZE as usize);
+    bytes_for_frame.extend_from_slice(&header.to_bytes());
+    for i in content.chunks(content_bytes_per_block) {
         bytes_for_frame.extend_from_slice(i);
         bytes_for_frame.extend_from_slice(ecc_encoder.encode(i).ecc());
     }
-    let result = build_frame(&bytes_for_frame[0..bytes_for_frame.len()], fps, width, height, colors, count, video_codec);
-    return result;
+    // the frame header stays where it is, it has to be read before anything else anyway
+    if layout.interleaved {
+        let blocks = content.len() / content_bytes_per_block;
+        let interleaved = interleave(&bytes_for_frame[FRAME_HEADER_SIZE..], blocks);
+        bytes_for_frame[FRAME_HEADER_SIZE..].copy_from_slice(&interleaved);
+    }
+    build_frame(&bytes_for_frame, layout)
 }
 
-/// Encodes any file into a video.
-pub fn encode(input: &str, output: &str, fps: u16, width: usize, height: usize, colors: u16, ecc_bytes: u8, video_codec: String, crf: u16, threads: usize) -> String {
-
-    let start_time = Instant::now();
+/// renders every frame on its own thread, the frames are returned in the same order as they were given
+fn render_frames(frames: Vec<(FrameHeader, Vec<u8>)>, options: &EncodeOptions) -> Result<Vec<Vec<u8>>> {
+    let mut thread_handles = Vec::with_capacity(frames.len());
+    for (header, content) in frames {
+        let (layout, ecc_bytes) = (options.layout(), options.ecc_bytes);
+        thread_handles.push(thread::spawn(move || {
+            prepare_build_image(&content, header, layout, ecc_bytes)
+        }));
+    }
+    thread_handles.into_iter().map(|t| t.join().unwrap()).collect()
+}
 
-    // create temp folder for saving the PNG and TS files
-    std::fs::create_dir_all(Path::new("tmp")).unwrap();
+/// fills (buf) as far as possible, only returning less than its length when we reached EOF
+fn read_full(file: &mut fs::File, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        let n = file.read(&mut buf[read..])?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    Ok(read)
+}
 
-    // open the file and gather information for the metadata frame
-    let mut file = fs::File::open(input).unwrap();
+/// checks the options before we start doing any expensive work
+fn validate_options(options: &EncodeOptions) -> Result<()> {
+    if !options.palette.supports(options.colors) {
+        return Err(VideobackupError::InvalidParameters(format!("{} colors are not supported by {} palettes, use a power of two from 2 to {}!",
+                                                               options.colors, options.palette, options.palette.max_colors())));
+    }
+    if options.ecc_bytes > BLOCK_SIZE / 2 {
+        return Err(VideobackupError::InvalidParameters(format!("You can't have more than {} ECC bytes!", BLOCK_SIZE / 2)));
+    }
+    if options.parity_group == 0 || options.parity_group as usize + options.parity_frames as usize > MAX_GROUP_FRAMES {
+        return Err(VideobackupError::InvalidParameters(format!("A parity group needs at least 1 data frame and can't have more than {} frames including its parity frames!", MAX_GROUP_FRAMES)));
+    }
+    if options.pixel_format == PixelFormat::Yuv420p && (!options.width.is_multiple_of(2) || !options.height.is_multiple_of(2)) {
+        return Err(VideobackupError::InvalidParameters(String::from("Width and height have to be even for yuv420p!")));
+    }
+    if [options.max_frames, options.max_duration, options.max_size].contains(&Some(0)) {
+        return Err(VideobackupError::InvalidParameters(String::from("The limits of a part have to be at least 1!")));
+    }
+    if options.fps == 0 || options.threads == 0 {
+        return Err(VideobackupError::InvalidParameters(String::from("FPS and threads have to be at least 1!")));
+    }
+    if options.pixel_size == 0 || options.pixel_size > MAX_PIXEL_SIZE {
+        return Err(VideobackupError::InvalidParameters(format!("The pixel size has to be between 1 and {}!", MAX_PIXEL_SIZE)));
+    }
+    // at least the first block of the metadata has to fit into the metadata frame
+    if options.layout().cells() < METADATA_BLOCK_SIZE * 8 || options.width > u32::MAX as usize || options.height > u32::MAX as usize {
+        return Err(VideobackupError::InvalidParameters(format!("A resolution of {}x{} can't be used with a pixel size of {}!", options.width, options.height, options.pixel_size)));
+    }
+    if options.layout().blocks_per_frame(FRAME_HEADER_SIZE) < 1 {
+        return Err(VideobackupError::InvalidParameters(String::from("The resolution is too small to fit a single block into a frame!")));
+    }
+    Ok(())
+}
 
-    let file_metadata = fs::metadata(input).unwrap();
+impl Encoder {
+    pub fn new(options: EncodeOptions) -> Self {
+        Encoder { options }
+    }
 
-    let file_size = file_metadata.len();
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
 
-    let file_name = Path::new(input).file_name().unwrap().to_str().unwrap();
-    if file_name.len() > 200 {
-        panic!("The input file name may not be longer than 200 characters!")
+    /// calculates the parity frames for (group) and hands them to ffmpeg, (first_sequence) is the sequence number of the first one
+    fn write_parity_frames(&self, ffmpeg: &mut FfmpegWriter, group: &[Vec<u8>], archive_id: [u8; 16], first_sequence: u64) -> Result<()> {
+        let options = &self.options;
+        let parities = parity::parity_frames(group, options.parity_group as usize, options.parity_frames as usize, group[0].len())?;
+        let prepared_frames: Vec<(FrameHeader, Vec<u8>)> = parities.into_iter().enumerate()
+                .map(|(i, p)| Ok((FrameHeader { archive_id, sequence: frame_sequence(first_sequence + i as u64)?, crc32: 0 }, p)))
+                .collect::<Result<Vec<(FrameHeader, Vec<u8>)>>>()?;
+        for frame in render_frames(prepared_frames, options)? {
+            ffmpeg.write_frame(&frame)?;
+        }
+        Ok(())
     }
 
-    if ecc_bytes > BLOCK_SIZE / 2 {
-        panic!("You can't have more than {} ECC bytes!", BLOCK_SIZE / 2);
+    /// Encodes any file or directory into a video, returns the absolute path of the video. If it got split into parts,
+    /// that's the path of the first one, `encode_parts` returns all of them.
+    pub fn encode<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q) -> Result<PathBuf> {
+        Ok(self.encode_parts(input, output)?.remove(0))
     }
 
-    let crc32 = crc32_file(input);
+    /// Encodes any file or directory into a video, which gets split into parts named like `backup.part1.mp4` if
+    /// (output) is `backup.mp4` and any of the part limits are set. Returns the absolute paths of the parts in order.
+    pub fn encode_parts<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q) -> Result<Vec<PathBuf>> {
+        let input = input.as_ref();
+        let output = output.as_ref();
+        let options = &self.options;
+        let threads = options.threads;
+
+        validate_options(options)?;
+        options.video_codec.validate(output, options.crf)?;
+
+        let start_time = Instant::now();
+
+        // gather information for the metadata frame
+        let file_metadata = fs::metadata(input)?;
+
+        // paths like "." don't have a name of their own
+        let absolute_input = input.absolutize()?.to_path_buf();
+        let file_name = match absolute_input.file_name().and_then(|n| n.to_str()) {
+            Some(v) => v,
+            None => return Err(VideobackupError::InvalidParameters(format!("{} doesn't have a valid UTF-8 file name!", input.display()))),
+        };
+        if file_name.len() > MAX_FILE_NAME_LENGTH {
+            return Err(VideobackupError::InvalidParameters(format!("The input file name may not be longer than {} bytes!", MAX_FILE_NAME_LENGTH)));
+        }
 
-    // calculate some geometry
-    let blocks_per_frame = (((width * height) as f32) / (256f32).log(colors as f32) / (BLOCK_SIZE as f32)) as usize;
-    let content_bytes_per_block = BLOCK_SIZE as usize - ecc_bytes as usize;
-    let content_bytes_per_frame = blocks_per_frame * content_bytes_per_block;
-    let needed_frames = ((file_size as f64 / content_bytes_per_frame as f64).ceil() as u64) + 1;
-
-    println!("→ Starting videobackup-rs encoder with following parameters:");
-    println!("  • FPS: {}", fps);
-    println!("  • Width: {}", width);
-    println!("  • Height: {}", height);
-    println!("  • Colors: {}", colors);
-    println!("  • ECC bytes: {}", ecc_bytes);
-    println!("  • Video codec: {}", video_codec);
-    println!("  • CRF: {}", crf);
-    println!("  • Threads: {}", threads);
-    println!("  • Needed frames: {}", needed_frames);
-
-    // metadata looks like this:
-    // - bytes 0-1 are the encoding version
-    // - bytes 2-3 are the palette size
-    // - byte 4 is the pixel size
-    // - bytes 5-12 is the file size
-    // - bytes 13-16 are the CRC32 checksum
-    // - byte 17 is the amount of ECC bytes
-    // - bytes 18-217 are the filename
-    // - bytes 218-249 are the ECC for the metadata frame
-
-    // make our metadata byte array for building the metadata frame
-    let mut metadata_bytes: [u8; 250] = [0; 250];
-    let ecc_encoder = Encoder::new(32);
-
-
-    // start copying all the stuff over
-    metadata_bytes[0..=1].copy_from_slice(&ENCODING_VERSION.to_be_bytes());
-    metadata_bytes[2..=3].copy_from_slice(&colors.to_be_bytes());
-    metadata_bytes[4] = 1; // TODO: change this when this tool allows for more than 2 colors
-    metadata_bytes[5..=12].copy_from_slice(&file_size.to_be_bytes());
-    metadata_bytes[13..=16].copy_from_slice(&crc32.to_be_bytes());
-    metadata_bytes[17] = ecc_bytes;
-    metadata_bytes[18..=(18 + file_name.len() - 1)].copy_from_slice(file_name.as_bytes());
-    let ecc =  ecc_encoder.encode(&metadata_bytes[0..=217]);
-    let bytes = ecc.ecc();
-    metadata_bytes[218..=249].copy_from_slice(bytes);
-    
-    // TODO: build metadata frame, implement encoding of the actual file
-    build_frame(&metadata_bytes, fps, width, height, 2, 0, video_codec);
-
-    // rename our metadata frame video so we can start building the data frames
-    match std::fs::rename(Path::new("tmp").join("0.ts"), Path::new("tmp").join("partial.ts")) {
-        Ok(v) => (),
-        Err(e) => panic!("Unable to rename the metadata frame to partial.ts! {}", e)
-    }
-
-    println!("→ Finished metadata frame; 1/{} ({:.1} %)", needed_frames, (100.0f32/needed_frames as f32));
-
-    // read (content_bytes_per_frame * threads) bytes of data, slice it and send it to the threads
-    let buffer_size = content_bytes_per_frame * threads;
-    let mut read_bytes: Vec<u8> = zero_vec(buffer_size);
-    let mut frame_count: u32 = 0;
-    while let Ok(n) = file.read(&mut read_bytes[..]) {
-        // this vector will at max contain (amount of threads) vectors of blocks that are ready for threads to chew through
-        // it will probably contain less than (amount of threads) vectors when we reached EOF
-        let mut prepared_frames: Vec<Vec<Vec<u8>>> = Vec::with_capacity(threads);
-        let mut threads_to_use = threads;
-        let mut reached_eof = false;
-        if n != buffer_size {
-            threads_to_use = ((n / content_bytes_per_frame) + 1).clamp(1, threads);
-            reached_eof = true;
+        // a directory gets packed into an archive, which then gets encoded like any other file
+        let archive_file = if file_metadata.is_dir() {
+            println!("→ Packing the directory into an archive...");
+            let temp = TempFile::next_to(output, "archive");
+            let (entries, skipped) = write_archive(input, &temp.path)?;
+            for path in skipped.iter() {
+                println!("⚠ {} is neither a file nor a directory, it's left out", path.display());
+            }
+            let directories = entries.iter().filter(|e| e.directory).count();
+            println!("→ Packed {} files and {} directories", entries.len() - directories, directories);
+            Some(temp)
         } else {
-            
+            None
+        };
+        let input = archive_file.as_ref().map(|t| t.path.as_path()).unwrap_or(input);
+        let file_size = fs::metadata(input)?.len();
+
+        let crc32 = crc32_file(input)?;
+        let hash = match options.hash {
+            Some(v) => Some((v, hash_file(v, input)?)),
+            None => None,
+        };
+
+        // the compressed size has to be in the metadata frame, which comes first, so the file gets compressed into a
+        // temporary file before anything else happens
+        let mut compressed_file: Option<TempFile> = None;
+        let mut compression: Option<(Compression, u64)> = None;
+        let mut data_size = file_size;
+        if let Some(v) = options.compression {
+            println!("→ Compressing the file with {}...", v);
+            let temp = TempFile::next_to(output, "compressed");
+            let compressed_size = compress_file(v, input, &temp.path)?;
+            if compressed_size < file_size {
+                println!("→ Compressed the file from {} to {} Bytes ({:.1} %)", file_size, compressed_size, compressed_size as f64 * 100.0 / file_size as f64);
+                compression = Some((v, file_size));
+                data_size = compressed_size;
+                compressed_file = Some(temp);
+            } else {
+                println!("⚠ Compressing the file with {} doesn't make it any smaller, it gets encoded as it is", v);
+            }
         }
-
-        // slice up frames for processing
-        for i in 0..threads_to_use {
-            let mut frame_vector: Vec<Vec<u8>> = Vec::with_capacity(blocks_per_frame);
-            for j in 0..blocks_per_frame {
-                let mut block: Vec<u8> = zero_vec(content_bytes_per_block);
-                block.copy_from_slice(&read_bytes[(i * content_bytes_per_frame + j * content_bytes_per_block)..(i * content_bytes_per_frame + (j+1) * content_bytes_per_block)]);
-                frame_vector.push(block);
+        let mut file = fs::File::open(compressed_file.as_ref().map(|t| t.path.as_path()).unwrap_or(input))?;
+
+        // calculate some geometry
+        let blocks_per_frame = options.layout().blocks_per_frame(FRAME_HEADER_SIZE);
+        let content_bytes_per_block = BLOCK_SIZE as usize - options.ecc_bytes as usize;
+        let content_bytes_per_frame = blocks_per_frame * content_bytes_per_block;
+
+        // an encrypted file is cut into chunks that fill exactly one frame each once they're encrypted, so a damaged
+        // frame only ever takes a single chunk with it
+        let mut file_key: Option<crypto::Key> = None;
+        let mut encryption: Option<Encryption> = None;
+        let mut chunk_size = content_bytes_per_frame;
+        if options.passphrase.is_some() || !options.recipients.is_empty() {
+            if content_bytes_per_frame <= AEAD_OVERHEAD {
+                return Err(VideobackupError::InvalidParameters(String::from("The frames are too small to hold any encrypted data!")));
+            }
+            // every recipient gets a copy of the random file key that only it can unlock
+            let key = crypto::random_key()?;
+            let mut recipients = Vec::with_capacity(options.recipients.len() + 1);
+            if let Some(passphrase) = &options.passphrase {
+                println!("→ Deriving the encryption key from the passphrase...");
+                recipients.push(crypto::passphrase_recipient(passphrase, &key)?);
+            }
+            for recipient in options.recipients.iter() {
+                recipients.push(crypto::x25519_recipient(recipient, &key)?);
             }
-            prepared_frames.push(frame_vector);
+            chunk_size = content_bytes_per_frame - AEAD_OVERHEAD;
+            encryption = Some(Encryption { chunk_size: chunk_size as u32, recipients, sealed: Vec::new() });
+            data_size = crypto::encrypted_size(data_size, chunk_size);
+            file_key = Some(key);
         }
 
-        // prepare some vectors and start multithreading
-        let mut thread_handles = Vec::with_capacity(threads);
-        let mut finished_frames: Vec<(u32, std::path::PathBuf)> = Vec::new(); // we use a tuple of the current_frame_count and the path because human sorting is awful und breaks everything when threads >= 10
-        let mut current_frame_count: u32 = 0;
-        for p in prepared_frames {
-            let handle = thread::spawn(move || {
-                return prepare_build_image(p, fps, width, height, colors, ecc_bytes, current_frame_count, String::from("libx264"));
-            });
-            thread_handles.push((current_frame_count, handle));
-            current_frame_count += 1;
-            frame_count += 1;
+        let data_frames = data_size.div_ceil(content_bytes_per_frame as u64);
+        let parity_group = options.parity_group as u64;
+        let parity_frames = options.parity_frames as u64;
+        // the metadata frame and the calibration frame come before all the others, a copy of the metadata frame comes last
+        let leading_frames: u64 = if options.calibration { 2 } else { 1 };
+
+        // with limits the video gets split into parts, each of them a video of its own with the same archive ID
+        let split = options.max_frames.is_some() || options.max_duration.is_some() || options.max_size.is_some();
+        let max_frames = options.max_frames.into_iter().chain(options.max_duration.map(|d| d.saturating_mul(options.fps as u64))).min();
+        let mut part_data_frames = match max_frames {
+            Some(v) => data_frames_per_part(v, leading_frames, parity_group, parity_frames),
+            None => u64::MAX,
+        };
+        if part_data_frames == 0 {
+            return Err(VideobackupError::InvalidParameters(format!("A part can't be longer than {} frames, that's not enough for a single {}!",
+                                                                   max_frames.unwrap(), if parity_frames > 0 { "parity group" } else { "data frame" })));
+        }
+        let parts = data_frames.div_ceil(part_data_frames).max(1);
+        // the parity frames are numbered after the data frames, the last one has to fit into a frame header
+        frame_sequence((data_frames + data_frames.div_ceil(parity_group) * parity_frames).saturating_sub(1))?;
+        let needed_frames = data_frames + data_frames.div_ceil(parity_group) * parity_frames + (leading_frames + 1) * parts;
+
+        println!("→ Starting videobackup-rs encoder with following parameters:");
+        println!("  • FPS: {}", options.fps);
+        println!("  • Width: {}", options.width);
+        println!("  • Height: {}", options.height);
+        println!("  • Colors: {}", options.colors);
+        println!("  • Palette: {}", options.palette);
+        println!("  • Pixel size: {}", options.pixel_size);
+        println!("  • ECC bytes: {}", options.ecc_bytes);
+        println!("  • Interleaving: {}", if options.interleave { "on" } else { "off" });
+        println!("  • Calibration frame: {}", if options.calibration { "yes" } else { "no" });
+        println!("  • Fiducials: {}", if options.fiducials { "yes" } else { "no" });
+        if let Some((v, _)) = compression {
+            println!("  • Compression: {}", v);
+        }
+        if let Some(encryption) = &encryption {
+            println!("  • Encryption: {}", crypto::describe_recipients(&encryption.recipients));
+            for recipient in options.recipients.iter() {
+                println!("  • Recipient: {}", recipient);
+            }
+        }
+        println!("  • Video codec: {}", options.video_codec);
+        println!("  • Pixel format: {}", options.video_codec.pixel_format(options.pixel_format));
+        if options.pixel_format == PixelFormat::Yuv420p && options.palette == PaletteFamily::Color && options.colors > 2 {
+            println!("ℹ yuv420p only keeps the colors at a quarter of the resolution, a grayscale palette survives that better");
+        }
+        match options.video_codec.crf_range() {
+            Some(_) => println!("  • CRF: {}", options.crf),
+            None => println!("  • CRF: lossless"),
+        }
+        if parity_frames > 0 {
+            println!("  • Parity frames: {} for every {} data frames", parity_frames, parity_group);
         }
-        for t in thread_handles {
-            finished_frames.push((t.0, t.1.join().unwrap()));
+        if let Some((algorithm, hash)) = &hash {
+            println!("  • {}: {}", algorithm, to_hex(hash));
+        }
+        if let Some(comment) = &options.comment {
+            println!("  • Comment: {}", comment);
+        }
+        println!("  • Threads: {}", threads);
+        println!("  • Needed frames: {}", needed_frames);
+        if split {
+            println!("  • Parts: {}{}", if options.max_size.is_some() { "at least " } else { "" }, parts);
         }
 
-        // prepare the list for ffmpeg to concatenate the generated frames
-        finished_frames.sort_by(|a, b| a.0.cmp(&b.0));
-        finished_frames.insert(0, (0, Path::new("tmp").join("partial.ts")));
-        let mut list = String::new();
-        for f in finished_frames.iter() {
-            list.push_str("file ");
-            list.push_str(&str::replace(f.1.to_str().unwrap(), "tmp/", ""));
-            list.push_str("\n");
+        let mut archive_id: [u8; 16] = [0; 16];
+        random_bytes(&mut archive_id)?;
+        let mut metadata = Metadata {
+            file_name: file_name.to_string(),
+            file_size: data_size,
+            crc32,
+            ecc_bytes: options.ecc_bytes,
+            palette: options.palette,
+            colors: options.colors,
+            pixel_size: options.pixel_size,
+            width: options.width as u32,
+            height: options.height as u32,
+            archive_id: Some(archive_id),
+            interleaved: options.interleave,
+            calibration: options.calibration,
+            fiducials: options.fiducials,
+            trailing_copy: true,
+            parity_group: options.parity_group,
+            parity_frames: options.parity_frames,
+            created: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()),
+            modified: file_metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()),
+            comment: options.comment.clone(),
+            hash,
+            compression,
+            encryption,
+            directory: archive_file.is_some(),
+            part: None,
+        };
+        if let Some(key) = &file_key {
+            metadata.seal(key);
+        }
+        let data = DataLayout { archive_id, content_bytes_per_frame, chunk_size, data_frames, leading_frames, file_key };
+
+        let mut outputs: Vec<PathBuf> = Vec::with_capacity(parts as usize);
+        let mut first_frame = 0;
+        while first_frame < data_frames || outputs.is_empty() {
+            let index = outputs.len();
+            let part_index = u16::try_from(index).map_err(|_| VideobackupError::InvalidParameters(format!("The video can't be split into more than {} parts!", u16::MAX as usize + 1)))?;
+            let frames = part_data_frames.min(data_frames - first_frame);
+            let (path, part) = if split {
+                println!("→ Encoding part {} with {} data frames", index + 1, frames);
+                (part_path(output, index), Some(Part { index: part_index, first_frame, data_frames: frames }))
+            } else {
+                (output.to_path_buf(), None)
+            };
+            self.encode_part(&path, &Metadata { part, ..metadata.clone() }, &mut file, &data, first_frame..(first_frame + frames))?;
+
+            if let Some(max_size) = options.max_size {
+                let size = fs::metadata(&path)?.len();
+                if size > max_size {
+                    // how large the frames get is up to the video codec, so all we can do is shrink the parts by how much
+                    // this one was too large and try again, with a bit of room to spare
+                    let smallest = if parity_frames > 0 { parity_group } else { 1 };
+                    if frames <= smallest {
+                        return Err(VideobackupError::InvalidParameters(format!("Part {} has {} Bytes with as few data frames as possible, it can't be made smaller than {} Bytes!",
+                                                                               index + 1, size, max_size)));
+                    }
+                    let shrunk = ((frames as f64 * max_size as f64 / size as f64 * 0.95) as u64 / smallest * smallest).max(smallest).min((frames - 1) / smallest * smallest);
+                    println!("⚠ Part {} has {} Bytes, more than {}, encoding it again with {} instead of {} data frames", index + 1, size, max_size, shrunk, frames);
+                    part_data_frames = shrunk;
+                    continue;
+                }
+            }
+            outputs.push(path.absolutize()?.to_path_buf());
+            first_frame += frames;
+        }
+        if split {
+            println!("→ Split the video into {} parts", outputs.len());
         }
-        let mut list_txt = fs::File::create(Path::new("tmp").join("list.txt")).unwrap();
-        list_txt.write(list.as_bytes()).unwrap();
 
-        // do the ffmpeg concatenation of all ts files and then rename everything to how we started
-        Command::new("ffmpeg")
-                .args(&["-y", "-f", "concat", "-r", &fps.to_string(), "-i", Path::new("tmp").join("list.txt").to_str().unwrap(), "-c", "copy", Path::new("tmp").join("new_partial.ts").to_str().unwrap()])
-                .output().unwrap();
+        println!("✓ Done in {} seconds!", (start_time.elapsed().as_millis() as f32 / 1000.0f32));
 
-        std::fs::remove_file(Path::new("tmp").join("list.txt")).unwrap();
-        std::fs::rename(Path::new("tmp").join("new_partial.ts"), Path::new("tmp").join("partial.ts")).unwrap();
+        Ok(outputs)
+    }
 
-        println!("→ Finished frames to {}/{} ({:.1} %)", frame_count + 1, needed_frames, ((((frame_count + 1) as f32 ) * 100.0f32)/needed_frames as f32));
+    /// encodes the data frames (frames) of (file) into the video (output), along with the metadata frame and everything
+    /// else a video needs
+    fn encode_part(&self, output: &Path, metadata: &Metadata, file: &mut fs::File, data: &DataLayout, frames: Range<u64>) -> Result<()> {
+        let options = &self.options;
+        let threads = options.threads;
+        let parity_group = options.parity_group as usize;
+        let parity_frames = options.parity_frames as usize;
+        let (archive_id, content_bytes_per_frame, chunk_size, leading_frames) = (data.archive_id, data.content_bytes_per_frame, data.chunk_size, data.leading_frames);
+        let part_frames = frames.end - frames.start;
+        let needed_frames = part_frames + part_frames.div_ceil(parity_group as u64) * parity_frames as u64 + leading_frames + 1;
+
+        let metadata_bytes = metadata.to_bytes()?;
+        let metadata_layout = options.layout().metadata();
+        if metadata_bytes.len() > metadata_layout.bytes() {
+            return Err(VideobackupError::InvalidParameters(format!("The metadata doesn't fit into a {}x{} frame with a pixel size of {}, use a shorter comment or file name!",
+                                                                   options.width, options.height, options.pixel_size)));
+        }
 
-        // some cleaning up
-        read_bytes = zero_vec(buffer_size);
-        if reached_eof {
-            break;
+        // every frame gets piped into a single ffmpeg process as raw RGB, so there's no need for any temporary files
+        let size = format!("{}x{}", options.width, options.height);
+        let fps = options.fps.to_string();
+        let output_str = output.to_string_lossy();
+        let codec_args = options.video_codec.ffmpeg_args(output, options.crf, options.pixel_format);
+        let mut args: Vec<&str> = vec!["-y", "-f", "rawvideo", "-pix_fmt", "rgb24", "-s", &size, "-r", &fps, "-i", "-"];
+        args.extend(codec_args.iter().map(|a| a.as_str()));
+        args.push(&output_str);
+        let mut ffmpeg = FfmpegWriter::spawn(&args)?;
+
+        // the metadata frame uses the same cells, the decoder finds them with the fiducials or tries every pixel size until
+        // it can read it. Without the metadata nothing can be decoded, so the frame holds several copies of it
+        let mut metadata_frame_bytes = zero_vec(metadata_layout.bytes());
+        for offset in metadata_offsets(metadata_layout.bytes(), metadata_bytes.len()) {
+            metadata_frame_bytes[offset..(offset + metadata_bytes.len())].copy_from_slice(&metadata_bytes);
         }
-    }
+        let metadata_frame = build_frame(&metadata_frame_bytes, metadata_layout)?;
+        ffmpeg.write_frame(&metadata_frame)?;
+
+        println!("→ Finished metadata frame; 1/{} ({:.1} %)", needed_frames, (100.0f32/needed_frames as f32));
 
-    println!("→ Finishing the final video...");
-    Command::new("ffmpeg")
-            .args(&["-y", "-r", &fps.to_string(), "-i", Path::new("tmp").join("partial.ts").to_str().unwrap(), "-c", "copy", output])
-            .output().unwrap();
+        if options.calibration {
+            let layout = options.layout();
+            ffmpeg.write_frame(&build_frame(&calibration_bytes(layout), layout)?)?;
+            println!("→ Finished calibration frame; 2/{} ({:.1} %)", needed_frames, (200.0f32/needed_frames as f32));
+        }
+
+        // read (chunk_size * threads) bytes of data, slice it and send it to the threads
+        file.seek(SeekFrom::Start(frames.start * chunk_size as u64))?;
+        let buffer_size = chunk_size * threads;
+        let mut read_bytes: Vec<u8> = zero_vec(buffer_size);
+        // the sequence number of the next data frame
+        let mut frame_count: u64 = frames.start;
+        // the data frames of the current parity group, they're only kept around when we need to calculate parity frames,
+        // parts always start with a new group
+        let mut group: Vec<Vec<u8>> = Vec::with_capacity(parity_group);
+        let first_group = frames.start / parity_group as u64;
+        let mut group_count: u64 = first_group;
+        while frame_count < frames.end {
+            let to_read = ((frames.end - frame_count) as usize).min(threads) * chunk_size;
+            let n = read_full(file, &mut read_bytes[0..to_read])?;
+            // this vector will at max contain (amount of threads) frames that are ready for threads to chew through
+            // it will probably contain less than (amount of threads) frames when we reached EOF
+            let threads_to_use = n.div_ceil(chunk_size);
+            if threads_to_use == 0 {
+                break;
+            }
 
-    println!("→ Cleaning up...");
-    std::fs::remove_dir_all(Path::new("tmp")).unwrap();
+            // slice up frames for processing
+            let mut prepared_frames: Vec<(FrameHeader, Vec<u8>)> = Vec::with_capacity(threads_to_use);
+            for i in 0..threads_to_use {
+                let sequence = frame_count + i as u64;
+                let header = FrameHeader { archive_id, sequence: frame_sequence(sequence)?, crc32: 0 };
+                let content = match &data.file_key {
+                    Some(key) => {
+                        let mut content = crypto::encrypt_chunk(key, sequence, sequence + 1 == data.data_frames, &read_bytes[(i * chunk_size)..((i+1) * chunk_size).min(n)]);
+                        content.resize(content_bytes_per_frame, 0);
+                        content
+                    },
+                    None => read_bytes[(i * chunk_size)..((i+1) * chunk_size)].to_vec(),
+                };
+                prepared_frames.push((header, content));
+            }
+            let contents: Vec<Vec<u8>> = if parity_frames > 0 {
+                prepared_frames.iter().map(|p| p.1.clone()).collect()
+            } else {
+                Vec::new()
+            };
+
+            // render the frames on multiple threads and hand them to ffmpeg in order
+            for frame in render_frames(prepared_frames, options)? {
+                ffmpeg.write_frame(&frame)?;
+                frame_count += 1;
+            }
+
+            // every full group of data frames gets followed by its parity frames
+            for content in contents {
+                group.push(content);
+                if group.len() == parity_group {
+                    self.write_parity_frames(&mut ffmpeg, &group, archive_id, data.data_frames + group_count * parity_frames as u64)?;
+                    group.clear();
+                    group_count += 1;
+                }
+            }
 
-    println!("✓ Done in {} seconds!", (start_time.elapsed().as_millis() as f32 / 1000.0f32));
+            let finished = frame_count - frames.start + (group_count - first_group) * parity_frames as u64 + leading_frames;
+            println!("→ Finished frames to {}/{} ({:.1} %)", finished, needed_frames, (((finished as f32) * 100.0f32)/needed_frames as f32));
 
-    return Path::new(input).absolutize().unwrap().to_str().unwrap().to_string();
-}
\ No newline at end of file
+            // some cleaning up
+            if n != to_read {
+                break;
+            }
+            read_bytes = zero_vec(buffer_size);
+        }
+
+        // the last group is probably not full, the missing frames are treated as if they only contained zeros
+        if !group.is_empty() {
+            self.write_parity_frames(&mut ffmpeg, &group, archive_id, data.data_frames + group_count * parity_frames as u64)?;
+        }
+
+        // in case the metadata frame gets damaged anyway
+        ffmpeg.write_frame(&metadata_frame)?;
+        println!("→ Finished the copy of the metadata frame; {}/{} (100.0 %)", needed_frames, needed_frames);
+
+        println!("→ Finishing the final video...");
+        ffmpeg.finish()
+    }
+}
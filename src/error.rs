@@ -0,0 +1,76 @@
+use crate::hash::HashAlgorithm;
+
+use std::fmt;
+use std::io;
+
+
+/// Everything that can go wrong while encoding or decoding.
+#[derive(Debug)]
+pub enum VideobackupError {
+    /// reading the input or writing the output failed
+    Io(io::Error),
+    /// ffmpeg or ffprobe couldn't be started, usually because they're not installed
+    FfmpegNotFound(String),
+    /// ffmpeg or ffprobe ran but exited unsuccessfully
+    Ffmpeg { stderr: String },
+    /// the metadata frame couldn't be read or contains nonsense
+    CorruptMetadata(String),
+    /// the manifest of a decoded directory doesn't make sense
+    CorruptArchive(String),
+    /// the video was encoded with an encoding version this version of videobackup can't read
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// the video uses a feature this version of videobackup doesn't know about, but needs to decode it
+    UnsupportedFeature(String),
+    /// the supplied options can't be used for encoding
+    InvalidParameters(String),
+    /// the decoded file doesn't match the checksum stored in the metadata
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// the decoded file doesn't match the hash stored in the metadata, both are in hex
+    HashMismatch { algorithm: HashAlgorithm, expected: String, actual: String },
+    /// the video is encrypted and neither a passphrase nor an identity for any of its recipients was given
+    KeyRequired,
+    /// none of the recipients of the encrypted video could be unlocked with the given passphrase or identities
+    WrongKey,
+}
+
+pub type Result<T> = std::result::Result<T, VideobackupError>;
+
+impl fmt::Display for VideobackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideobackupError::Io(e) => write!(f, "I/O error: {}", e),
+            VideobackupError::FfmpegNotFound(program) => write!(f, "Unable to run {}, make sure it's installed and in your PATH", program),
+            VideobackupError::Ffmpeg { stderr } => write!(f, "ffmpeg failed: {}", stderr.trim()),
+            VideobackupError::CorruptMetadata(reason) => write!(f, "The metadata frame is corrupted: {}", reason),
+            VideobackupError::CorruptArchive(reason) => write!(f, "The manifest of the directory is corrupted: {}", reason),
+            VideobackupError::UnsupportedVersion { found, supported } => {
+                if found < supported {
+                    write!(f, "Encoding version {} is not compatible with this videobackup version's encoding version ({}). Obtain an earlier version of videobackup and try again.", found, supported)
+                } else {
+                    write!(f, "Encoding version {} is not compatible with this videobackup version's encoding version ({}). Obtain a newer version of videobackup and try again.", found, supported)
+                }
+            },
+            VideobackupError::UnsupportedFeature(feature) => write!(f, "The video uses {}, which this version of videobackup doesn't support. Obtain a newer version of videobackup and try again.", feature),
+            VideobackupError::InvalidParameters(reason) => write!(f, "Invalid parameters: {}", reason),
+            VideobackupError::ChecksumMismatch { expected, actual } => write!(f, "CRC32 check unsuccessful (expected {}, got {}), your file is likely corrupted", expected, actual),
+            VideobackupError::HashMismatch { algorithm, expected, actual } => write!(f, "{} check unsuccessful (expected {}, got {}), your file is likely corrupted or was tampered with", algorithm, expected, actual),
+            VideobackupError::KeyRequired => write!(f, "The video is encrypted, a passphrase or an identity it was encrypted for is needed to decode it"),
+            VideobackupError::WrongKey => write!(f, "The passphrase is wrong or the video wasn't encrypted for any of the identities, or the part of the metadata they unlock is damaged beyond repair"),
+        }
+    }
+}
+
+impl std::error::Error for VideobackupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VideobackupError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VideobackupError {
+    fn from(e: io::Error) -> Self {
+        VideobackupError::Io(e)
+    }
+}
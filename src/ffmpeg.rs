@@ -0,0 +1,199 @@
+use crate::error::{Result, VideobackupError};
+
+use std::io;
+use std::io::prelude::*;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Output, Stdio};
+use std::thread;
+use std::thread::JoinHandle;
+
+
+/// runs ffmpeg or ffprobe (program) with the given arguments and turns every kind of failure into a VideobackupError
+pub fn run(program: &str, args: &[&str]) -> Result<Output> {
+    let output = match Command::new(program).args(args).output() {
+        Ok(v) => v,
+        Err(e) => return Err(spawn_error(program, e)),
+    };
+    if !output.status.success() {
+        return Err(VideobackupError::Ffmpeg { stderr: String::from_utf8_lossy(&output.stderr).to_string() });
+    }
+    Ok(output)
+}
+
+fn spawn_error(program: &str, e: io::Error) -> VideobackupError {
+    if e.kind() == io::ErrorKind::NotFound {
+        VideobackupError::FfmpegNotFound(String::from(program))
+    } else {
+        VideobackupError::Io(e)
+    }
+}
+
+/// asks ffprobe for the width and height of the first video stream in (input)
+pub fn probe_dimensions(input: &str) -> Result<(usize, usize)> {
+    let output = run("ffprobe", &["-v", "error", "-select_streams", "v:0", "-show_entries", "stream=width,height", "-of", "csv=p=0", input])?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let dimensions: Vec<usize> = stdout.split(',').filter_map(|d| d.trim().parse::<usize>().ok()).collect();
+    if dimensions.len() != 2 || dimensions[0] == 0 || dimensions[1] == 0 {
+        return Err(VideobackupError::Ffmpeg { stderr: format!("ffprobe returned invalid dimensions: {}", stdout) });
+    }
+    Ok((dimensions[0], dimensions[1]))
+}
+
+/// decodes the end of (input) and returns its very last frame of (frame_size) bytes, None if it doesn't have any
+pub fn read_last_frame(input: &str, frame_size: usize) -> Result<Option<Vec<u8>>> {
+    // a few seconds are plenty even at low frame rates, seeking lands on a keyframe before that anyway
+    let mut ffmpeg = FfmpegReader::spawn_end(input, 5)?;
+    let mut frame = vec![0; frame_size];
+    let mut last = None;
+    while ffmpeg.read_frame(&mut frame)? {
+        last = Some(frame.clone());
+    }
+    Ok(last)
+}
+
+/// collects everything (child) writes to stderr in a separate thread, so a chatty ffmpeg can't block on a full pipe
+fn collect_stderr(child: &mut Child) -> Option<JoinHandle<String>> {
+    child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf);
+            String::from_utf8_lossy(&buf).to_string()
+        })
+    })
+}
+
+/// A long-lived ffmpeg process that gets raw frames written to its stdin.
+pub struct FfmpegWriter {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl FfmpegWriter {
+    /// starts ffmpeg with the given arguments, they should tell it to read from "-"
+    pub fn spawn(args: &[&str]) -> Result<Self> {
+        let mut child = match Command::new("ffmpeg").args(args).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn() {
+            Ok(v) => v,
+            Err(e) => return Err(spawn_error("ffmpeg", e)),
+        };
+        let stdin = child.stdin.take();
+        let stderr = collect_stderr(&mut child);
+        Ok(FfmpegWriter { child, stdin, stderr })
+    }
+
+    /// hands a single frame to ffmpeg, if ffmpeg already died we return its error output
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let written = match self.stdin.as_mut() {
+            Some(stdin) => stdin.write_all(frame),
+            None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        };
+        match written {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                self.stdin = None;
+                self.wait()?;
+                // ffmpeg closed its stdin but exited successfully, that shouldn't happen
+                Err(VideobackupError::Ffmpeg { stderr: String::from("ffmpeg stopped accepting frames") })
+            },
+            Err(e) => Err(VideobackupError::Io(e)),
+        }
+    }
+
+    /// closes ffmpeg's stdin and waits for it to finish writing the video
+    pub fn finish(mut self) -> Result<()> {
+        self.stdin = None;
+        self.wait()
+    }
+
+    fn wait(&mut self) -> Result<()> {
+        let status = self.child.wait()?;
+        let stderr = match self.stderr.take() {
+            Some(handle) => handle.join().unwrap_or_default(),
+            None => String::new(),
+        };
+        if !status.success() {
+            return Err(VideobackupError::Ffmpeg { stderr });
+        }
+        Ok(())
+    }
+}
+
+/// A long-lived ffmpeg process that decodes a video into raw RGB24 frames on its stdout.
+pub struct FfmpegReader {
+    child: Child,
+    stdout: Option<ChildStdout>,
+    stderr: Option<JoinHandle<String>>,
+}
+
+impl FfmpegReader {
+    /// starts decoding (input) into one continuous rawvideo stream
+    pub fn spawn(input: &str) -> Result<Self> {
+        Self::start(&["-v", "error", "-i", input])
+    }
+
+    /// starts decoding only the last (seconds) seconds of (input)
+    pub fn spawn_end(input: &str, seconds: u32) -> Result<Self> {
+        let start = format!("-{}", seconds);
+        Self::start(&["-v", "error", "-sseof", &start, "-i", input])
+    }
+
+    /// starts ffmpeg with the given input arguments, it writes the first video stream to its stdout
+    fn start(input_args: &[&str]) -> Result<Self> {
+        let mut child = match Command::new("ffmpeg").args(input_args).args(["-map", "0:v:0", "-vsync", "0", "-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+                                                   .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(v) => v,
+            Err(e) => return Err(spawn_error("ffmpeg", e)),
+        };
+        let stdout = child.stdout.take();
+        let stderr = collect_stderr(&mut child);
+        Ok(FfmpegReader { child, stdout, stderr })
+    }
+
+    /// fills (frame) with the next frame, returns false once the video ended
+    pub fn read_frame(&mut self, frame: &mut [u8]) -> Result<bool> {
+        let stdout = match self.stdout.as_mut() {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let mut read = 0;
+        while read < frame.len() {
+            let n = stdout.read(&mut frame[read..])?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        if read == frame.len() {
+            return Ok(true);
+        }
+        // we reached the end of the stream, a partial frame at the end is nothing we can work with
+        self.stdout = None;
+        let status = self.child.wait()?;
+        let stderr = match self.stderr.take() {
+            Some(handle) => handle.join().unwrap_or_default(),
+            None => String::new(),
+        };
+        if !status.success() {
+            return Err(VideobackupError::Ffmpeg { stderr });
+        }
+        Ok(false)
+    }
+}
+
+impl Drop for FfmpegReader {
+    fn drop(&mut self) {
+        if self.stdout.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+impl Drop for FfmpegWriter {
+    fn drop(&mut self) {
+        // we got dropped without finishing (e.g. because of an error), don't leave a zombie process behind
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
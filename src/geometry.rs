@@ -0,0 +1,381 @@
+/// how many rings of cells the fiducials take up on every side of a frame: 2 rings of black margin that survive slight
+/// cropping, a white border the decoder looks for and a ring with the timing pattern on the top and left side, which
+/// stays black on the right and bottom side
+pub const FIDUCIAL_CELLS: usize = 4;
+
+/// the ring of the white border
+const BORDER: usize = 2;
+
+/// the ring of the timing pattern
+const TIMING: usize = 3;
+
+/// a pixel at least this bright might belong to the white border
+const BRIGHT: f32 = 128.0;
+
+/// how many of the cells of the border and the timing pattern have to look like they should before we believe we found the grid
+const MIN_MATCHING_FIDUCIALS: f32 = 0.9;
+
+/// whether the cell in column (x) and row (y) of a (columns)x(rows) grid is white in the fiducials, the timing pattern
+/// alternates between black and white cells starting with a black one, so the decoder can count the columns and rows
+pub fn fiducial_white(x: usize, y: usize, columns: usize, rows: usize) -> bool {
+    let border = (BORDER..columns - BORDER).contains(&x) && (BORDER..rows - BORDER).contains(&y)
+              && (x == BORDER || y == BORDER || x == columns - 1 - BORDER || y == rows - 1 - BORDER);
+    let timing = (y == TIMING && (TIMING..columns - TIMING - 1).contains(&x) && x.is_multiple_of(2))
+              || (x == TIMING && (TIMING..rows - TIMING - 1).contains(&y) && y.is_multiple_of(2));
+    border || timing
+}
+
+/// whether the cell in column (x) and row (y) of a (columns)x(rows) grid belongs to the border or the timing pattern,
+/// the margin is black like everything around the frame so it doesn't tell us anything
+fn is_pattern(x: usize, y: usize, columns: usize, rows: usize) -> bool {
+    let ring = x.min(y).min(columns - 1 - x).min(rows - 1 - y);
+    ring == BORDER || ring == TIMING
+}
+
+/// the brightness of a pixel
+fn luma(frame: &[u8], width: usize, x: usize, y: usize) -> f32 {
+    let pixel = (y * width + x) * 3;
+    0.299 * frame[pixel] as f32 + 0.587 * frame[pixel + 1] as f32 + 0.114 * frame[pixel + 2] as f32
+}
+
+/// how long the longest run of bright pixels is
+fn longest_bright_run<I: Iterator<Item = f32>>(lumas: I) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for luma in lumas {
+        current = if luma >= BRIGHT { current + 1 } else { 0 };
+        longest = longest.max(current);
+    }
+    longest
+}
+
+/// where the bright line at (start) of (profile) ends when going in (direction), with sub-pixel precision. The edge is
+/// where the brightness falls below (threshold)
+fn edge(profile: &[f32], start: usize, direction: isize, threshold: f32) -> f32 {
+    let boundary = if direction < 0 { 0.0 } else { profile.len() as f32 };
+    let step = |i: usize| -> Option<usize> {
+        let next = i as isize + direction;
+        if next < 0 || next as usize >= profile.len() { None } else { Some(next as usize) }
+    };
+    let mut inner = start;
+    let mut outer = match step(inner) {
+        Some(v) => v,
+        None => return boundary,
+    };
+    while profile[outer] >= threshold {
+        inner = outer;
+        outer = match step(inner) {
+            Some(v) => v,
+            None => return boundary,
+        };
+    }
+    // the brightness changes linearly between the centers of the two pixels
+    let fraction = ((profile[inner] - threshold) / (profile[inner] - profile[outer]).max(f32::EPSILON)).clamp(0.0, 1.0);
+    inner as f32 + 0.5 + direction as f32 * fraction
+}
+
+/// the positions in (profile) where it crosses (threshold), with sub-pixel precision
+fn crossings(profile: &[f32], threshold: f32) -> Vec<f32> {
+    profile.windows(2).enumerate()
+            .filter(|(_, w)| (w[0] >= threshold) != (w[1] >= threshold))
+            .map(|(i, w)| i as f32 + 0.5 + (threshold - w[0]) / (w[1] - w[0]))
+            .collect()
+}
+
+/// how large a cell is, from the (crossings) of a timing pattern that are all one cell apart
+fn cell_size(crossings: &[f32]) -> Option<f32> {
+    if crossings.len() < 3 {
+        return None;
+    }
+    // the median distance doesn't care about a few crossings that noise added or removed, it's only precise to about a
+    // pixel though, so it's only used to tell how many cells every distance spans
+    let distances: Vec<f32> = crossings.windows(2).map(|w| w[1] - w[0]).collect();
+    let mut sorted = distances.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = sorted[sorted.len() / 2];
+    let cells: f32 = distances.iter().map(|d| (d / median).round()).sum();
+    if cells < 1.0 {
+        return None;
+    }
+    Some((crossings[crossings.len() - 1] - crossings[0]) / cells)
+}
+
+/// how many cells there are along an axis whose border is (extent) pixels long and starts at (start), that's the amount
+/// of cells that makes the timing pattern (timing) (the brightness along the axis) line up best. The (rough) cell size
+/// from the crossings of the timing pattern is only good enough to tell roughly how many there are, the smallest error
+/// adds up over thousands of cells
+fn count_cells(timing: &[f32], start: f32, extent: f32, rough: f32, threshold: f32) -> Option<usize> {
+    let estimate = (extent / rough).round() as usize + 2 * BORDER;
+    let spread = estimate / 32 + 2;
+    (estimate.saturating_sub(spread).max(2 * FIDUCIAL_CELLS + 1)..=(estimate + spread))
+            .map(|cells| {
+                let size = extent / (cells - 2 * BORDER) as f32;
+                let pattern = TIMING..(cells - TIMING - 1);
+                let matching = pattern.clone().filter(|&i| {
+                    let center = start + (i - BORDER) as f32 * size + size / 2.0;
+                    let luma = if center < 0.0 { 0.0 } else { timing.get(center as usize).copied().unwrap_or(0.0) };
+                    (luma >= threshold) == i.is_multiple_of(2)
+                }).count();
+                (cells, matching as f32 / pattern.len() as f32)
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(cells, _)| cells)
+}
+
+/// Where the grid of cells is in a frame that might have been scaled, letterboxed or slightly cropped on its way
+/// through some platform, found with the fiducials around the grid.
+#[derive(Clone, Copy, Debug)]
+pub struct Geometry {
+    /// the size of the frames as they arrived
+    width: usize,
+    height: usize,
+    /// where the top left corner of the grid is, it might be outside of the frame if the video got cropped
+    left: f32,
+    top: f32,
+    cell_width: f32,
+    cell_height: f32,
+    /// the size of the grid, including the fiducials
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Geometry {
+    /// looks for the fiducials in a (width)x(height) frame, returns None if there aren't any
+    pub fn locate(frame: &[u8], width: usize, height: usize) -> Option<Geometry> {
+        if width < 2 * FIDUCIAL_CELLS || height < 2 * FIDUCIAL_CELLS {
+            return None;
+        }
+        // the white border is the outermost long bright line on every side, letterboxing only adds black around it
+        let bright_row = |y: usize| longest_bright_run((0..width).map(|x| luma(frame, width, x, y))) >= width / 3;
+        let bright_column = |x: usize| longest_bright_run((0..height).map(|y| luma(frame, width, x, y))) >= height / 3;
+        let top = (0..height / 2).find(|&y| bright_row(y))?;
+        let bottom = (height / 2..height).rev().find(|&y| bright_row(y))?;
+        let left = (0..width / 2).find(|&x| bright_column(x))?;
+        let right = (width / 2..width).rev().find(|&x| bright_column(x))?;
+
+        // the average brightness of every column and row in the middle of the grid, it's where the edges of the border
+        // get measured precisely
+        let (band_top, band_bottom) = (top + (bottom - top) / 4, bottom - (bottom - top) / 4);
+        let (band_left, band_right) = (left + (right - left) / 4, right - (right - left) / 4);
+        let column_profile: Vec<f32> = (0..width)
+                .map(|x| (band_top..=band_bottom).map(|y| luma(frame, width, x, y)).sum::<f32>() / (band_bottom - band_top + 1) as f32)
+                .collect();
+        let row_profile: Vec<f32> = (0..height)
+                .map(|y| (band_left..=band_right).map(|x| luma(frame, width, x, y)).sum::<f32>() / (band_right - band_left + 1) as f32)
+                .collect();
+        // the border is the brightest thing around its edge, the margin or the letterboxing the darkest
+        let peak = column_profile[left..(left + 3).min(width)].iter().cloned().fold(0.0, f32::max);
+        let dark = column_profile[left.saturating_sub(2)..left].iter().cloned().fold(peak, f32::min);
+        let threshold = (peak + dark.min(BRIGHT)) / 2.0;
+        let outer_left = edge(&column_profile, left, -1, threshold);
+        let outer_right = edge(&column_profile, right, 1, threshold);
+        let outer_top = edge(&row_profile, top, -1, threshold);
+        let outer_bottom = edge(&row_profile, bottom, 1, threshold);
+        // the right and bottom border only have black around them, so they show roughly how large a cell is
+        let rough_width = (outer_right - edge(&column_profile, right, -1, threshold)).max(1.0);
+        let rough_height = (outer_bottom - edge(&row_profile, bottom, -1, threshold)).max(1.0);
+
+        // the timing patterns run through the middle of the ring inside the top and left border, every crossing between
+        // black and white is the edge of a cell
+        let timing_row = ((outer_top + 1.5 * rough_height) as usize).min(height - 1);
+        let timing_column = ((outer_left + 1.5 * rough_width) as usize).min(width - 1);
+        let horizontal: Vec<f32> = (0..width).map(|x| luma(frame, width, x, timing_row)).collect();
+        let vertical: Vec<f32> = (0..height).map(|y| luma(frame, width, timing_column, y)).collect();
+        let (from, to) = ((outer_left + 1.5 * rough_width) as usize, ((outer_right - 1.5 * rough_width).max(0.0) as usize).min(width));
+        let (from_y, to_y) = ((outer_top + 1.5 * rough_height) as usize, ((outer_bottom - 1.5 * rough_height).max(0.0) as usize).min(height));
+        let cell_width = cell_size(&crossings(&horizontal[from.min(to)..to], threshold))?;
+        let cell_height = cell_size(&crossings(&vertical[from_y.min(to_y)..to_y], threshold))?;
+
+        // the border runs along the outer edge of the cells in ring (BORDER), so it's (columns - 2 * BORDER) cells wide
+        let columns = count_cells(&horizontal, outer_left, outer_right - outer_left, cell_width, threshold)?;
+        let rows = count_cells(&vertical, outer_top, outer_bottom - outer_top, cell_height, threshold)?;
+        if columns <= 2 * FIDUCIAL_CELLS || rows <= 2 * FIDUCIAL_CELLS {
+            return None;
+        }
+        let cell_width = (outer_right - outer_left) / (columns - 2 * BORDER) as f32;
+        let cell_height = (outer_bottom - outer_top) / (rows - 2 * BORDER) as f32;
+        let geometry = Geometry {
+            width,
+            height,
+            left: outer_left - BORDER as f32 * cell_width,
+            top: outer_top - BORDER as f32 * cell_height,
+            cell_width,
+            cell_height,
+            columns,
+            rows,
+        };
+
+        // make sure the fiducials are where we think they are, a frame without any could have fooled us
+        let resampled = geometry.resample(frame);
+        let mut patterns = 0;
+        let mut matching = 0;
+        for y in 0..rows {
+            for x in 0..columns {
+                if !is_pattern(x, y, columns, rows) {
+                    continue;
+                }
+                patterns += 1;
+                if (luma(&resampled, columns, x, y) >= threshold) == fiducial_white(x, y, columns, rows) {
+                    matching += 1;
+                }
+            }
+        }
+        if (matching as f32) < patterns as f32 * MIN_MATCHING_FIDUCIALS {
+            return None;
+        }
+        Some(geometry)
+    }
+
+    /// the pixels whose centers lie in the inner half of cell (index) along an axis, compression smears the colors of
+    /// neighboring cells into the edges
+    fn inner_pixels(start: f32, size: f32, index: usize, pixels: usize) -> std::ops::Range<usize> {
+        let from = start + size * (index as f32 + 0.25) - 0.5;
+        let to = start + size * (index as f32 + 0.75) - 0.5;
+        let (first, last) = (from.ceil(), to.floor());
+        // cells of less than 2 pixels only get the pixel closest to their center
+        let (first, last) = if first > last {
+            let center = (start + size * (index as f32 + 0.5)).floor();
+            (center, center)
+        } else {
+            (first, last)
+        };
+        if last < 0.0 || first >= pixels as f32 {
+            return 0..0;
+        }
+        (first.max(0.0) as usize)..((last as usize + 1).min(pixels))
+    }
+
+    /// turns a frame as it arrived into a frame with one pixel for every cell of the grid, holding the average color of
+    /// the cell; cells outside of the frame are black
+    pub fn resample(&self, frame: &[u8]) -> Vec<u8> {
+        let columns: Vec<std::ops::Range<usize>> = (0..self.columns).map(|x| Self::inner_pixels(self.left, self.cell_width, x, self.width)).collect();
+        let rows: Vec<std::ops::Range<usize>> = (0..self.rows).map(|y| Self::inner_pixels(self.top, self.cell_height, y, self.height)).collect();
+        let mut resampled: Vec<u8> = vec![0; self.columns * self.rows * 3];
+        for (y, row_pixels) in rows.iter().enumerate() {
+            for (x, column_pixels) in columns.iter().enumerate() {
+                let mut sum: [u32; 3] = [0; 3];
+                let mut count: u32 = 0;
+                for row in row_pixels.clone() {
+                    for column in column_pixels.clone() {
+                        let pixel = (row * self.width + column) * 3;
+                        for c in 0..3 {
+                            sum[c] += frame[pixel + c] as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    continue;
+                }
+                let cell = (y * self.columns + x) * 3;
+                for c in 0..3 {
+                    resampled[cell + c] = ((sum[c] + count / 2) / count) as u8;
+                }
+            }
+        }
+        resampled
+    }
+
+    /// whether the grid got stretched or moved at all
+    pub fn is_scaled(&self, pixel_size: u8) -> bool {
+        let pixel_size = pixel_size as f32;
+        (self.cell_width - pixel_size).abs() > 0.01 || (self.cell_height - pixel_size).abs() > 0.01
+                || self.left.abs() > 0.01 || self.top.abs() > 0.01
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::FrameLayout;
+    use crate::encode::build_frame;
+    use crate::palette::PaletteFamily;
+
+    const LAYOUT: FrameLayout = FrameLayout { width: 192, height: 128, colors: 8, palette: PaletteFamily::Color, pixel_size: 2,
+                                              interleaved: false, fiducials: true };
+
+    /// a frame full of bytes with its fiducials, and one pixel for every cell of it
+    fn frame() -> (Vec<u8>, Vec<u8>) {
+        let bytes: Vec<u8> = (0..LAYOUT.bytes()).map(|i| (i * 151 + 7) as u8).collect();
+        let frame = build_frame(&bytes, LAYOUT).unwrap();
+        let (columns, rows) = (LAYOUT.grid_columns(), LAYOUT.grid_rows());
+        let pixel_size = LAYOUT.pixel_size as usize;
+        let cells = (0..rows).flat_map(|y| (0..columns).map(move |x| (x, y)))
+                .flat_map(|(x, y)| {
+                    let pixel = (y * pixel_size * LAYOUT.width + x * pixel_size) * 3;
+                    frame[pixel..(pixel + 3)].to_vec()
+                })
+                .collect();
+        (frame, cells)
+    }
+
+    /// scales (frame) by (scale) the way video scalers do, blending neighboring pixels, moves it by (left) and (top)
+    /// pixels and puts it into a (width)x(height) frame, it's letterboxed if it's smaller and cropped if it doesn't fit
+    fn transform(frame: &[u8], scale: f32, left: isize, top: isize, width: usize, height: usize) -> Vec<u8> {
+        let mut transformed = vec![0; width * height * 3];
+        for y in 0..height {
+            for x in 0..width {
+                let source_x = ((x as isize - left) as f32 + 0.5) / scale - 0.5;
+                let source_y = ((y as isize - top) as f32 + 0.5) / scale - 0.5;
+                if source_x < -0.5 || source_y < -0.5 || source_x > LAYOUT.width as f32 - 0.5 || source_y > LAYOUT.height as f32 - 0.5 {
+                    continue;
+                }
+                let (x0, y0) = (source_x.floor().max(0.0) as usize, source_y.floor().max(0.0) as usize);
+                let (x1, y1) = ((x0 + 1).min(LAYOUT.width - 1), (y0 + 1).min(LAYOUT.height - 1));
+                let (fx, fy) = ((source_x - x0 as f32).clamp(0.0, 1.0), (source_y - y0 as f32).clamp(0.0, 1.0));
+                for c in 0..3 {
+                    let at = |x: usize, y: usize| frame[(y * LAYOUT.width + x) * 3 + c] as f32;
+                    let value = (at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx) * (1.0 - fy) + (at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx) * fy;
+                    transformed[(y * width + x) * 3 + c] = value.round() as u8;
+                }
+            }
+        }
+        transformed
+    }
+
+    #[test]
+    fn grid_is_found() {
+        let (frame, cells) = frame();
+        // as it was encoded, scaled up and letterboxed, and scaled up by odd factors and cropped into the black margin
+        for (scale, left, top, width, height) in [(1.0, 0, 0, 192, 128), (1.5, 20, 16, 328, 224), (1.25, -3, -2, 236, 156), (1.3, -3, -2, 245, 163)] {
+            let transformed = transform(&frame, scale, left, top, width, height);
+            let geometry = Geometry::locate(&transformed, width, height).unwrap();
+            assert_eq!((geometry.columns, geometry.rows), (LAYOUT.grid_columns(), LAYOUT.grid_rows()), "{}", scale);
+            assert_eq!(geometry.resample(&transformed), cells, "{}", scale);
+            assert_eq!(geometry.is_scaled(LAYOUT.pixel_size), (scale, left, top) != (1.0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn no_fiducials() {
+        let layout = FrameLayout { fiducials: false, ..LAYOUT };
+        let bytes: Vec<u8> = (0..layout.bytes()).map(|i| (i * 151 + 7) as u8).collect();
+        assert!(Geometry::locate(&build_frame(&bytes, layout).unwrap(), layout.width, layout.height).is_none());
+        assert!(Geometry::locate(&vec![0; LAYOUT.width * LAYOUT.height * 3], LAYOUT.width, LAYOUT.height).is_none());
+    }
+
+    #[test]
+    fn cell_sizes() {
+        let crossings: Vec<f32> = (0..20).map(|i| 10.0 + i as f32 * 3.5).collect();
+        assert!((cell_size(&crossings).unwrap() - 3.5).abs() < 0.001);
+        // a crossing that got lost doesn't change the size
+        let missing: Vec<f32> = crossings.iter().cloned().enumerate().filter(|(i, _)| *i != 7).map(|(_, c)| c).collect();
+        assert!((cell_size(&missing).unwrap() - 3.5).abs() < 0.001);
+        assert!(cell_size(&crossings[..2]).is_none());
+    }
+
+    #[test]
+    fn cells_are_counted() {
+        // the timing pattern of 100 cells of 2.5 pixels whose border starts at pixel 5
+        let (cells, size, start) = (100, 2.5, 5.0);
+        let extent = (cells - 2 * BORDER) as f32 * size;
+        let timing: Vec<f32> = (0..260).map(|p| {
+            let cell = ((p as f32 + 0.5 - start) / size).floor() as isize + BORDER as isize;
+            let white = cell >= TIMING as isize && cell < (cells - TIMING - 1) as isize && cell % 2 == 0;
+            if white { 255.0 } else { 0.0 }
+        }).collect();
+        // the rough size is a little off, which would be a few cells off after 100 of them
+        assert_eq!(count_cells(&timing, start, extent, 2.55, BRIGHT), Some(cells));
+        assert_eq!(count_cells(&timing, start, extent, 2.45, BRIGHT), Some(cells));
+    }
+}
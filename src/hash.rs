@@ -0,0 +1,133 @@
+extern crate blake3;
+extern crate sha2;
+
+use crate::error::{Result, VideobackupError};
+
+use sha2::{Digest, Sha256};
+
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+
+
+/// The cryptographic hashes the encoder can store in the metadata, unlike the CRC32 they also catch deliberate changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    /// a lot faster than SHA-256, especially for large files
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// the byte the algorithm is stored as in the metadata
+    pub fn to_byte(self) -> u8 {
+        match self {
+            HashAlgorithm::Sha256 => 0,
+            HashAlgorithm::Blake3 => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(HashAlgorithm::Sha256),
+            1 => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// how many bytes a hash of this algorithm has
+    pub fn length(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Blake3 => "BLAKE3",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = VideobackupError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(VideobackupError::InvalidParameters(format!("{} is not a hash algorithm, use sha256, blake3 or none", s))),
+        }
+    }
+}
+
+/// the hash of the file (filename), read in 1MiB pieces so it never has to fit into memory
+pub fn hash_file<P: AsRef<Path>>(algorithm: HashAlgorithm, filename: P) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(filename)?;
+    let mut sha256 = Sha256::new();
+    let mut blake3 = blake3::Hasher::new();
+
+    const BUF_SIZE: usize = 1024*1024;
+    let mut buf = vec![0; BUF_SIZE];
+    loop {
+        let n = file.read(&mut buf[..])?;
+        if n == 0 {
+            break;
+        }
+        match algorithm {
+            HashAlgorithm::Sha256 => sha256.update(&buf[0..n]),
+            HashAlgorithm::Blake3 => {
+                blake3.update(&buf[0..n]);
+            },
+        }
+    }
+    Ok(match algorithm {
+        HashAlgorithm::Sha256 => sha256.finalize().to_vec(),
+        HashAlgorithm::Blake3 => blake3.finalize().as_bytes().to_vec(),
+    })
+}
+
+/// (bytes) as lowercase hex, the way hashes are usually written down
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TempFile;
+
+    /// a temporary file holding (contents) that's removed again once it's dropped
+    fn file_of(name: &str, contents: &[u8]) -> TempFile {
+        let file = TempFile::next_to(&std::env::temp_dir().join(format!("videobackup-rs-test-{}", std::process::id())), name);
+        fs::write(&file.path, contents).unwrap();
+        file
+    }
+
+    #[test]
+    fn known_hashes() {
+        let empty = file_of("hash-empty", b"");
+        let abc = file_of("hash-abc", b"abc");
+        assert_eq!(to_hex(&hash_file(HashAlgorithm::Sha256, &empty.path).unwrap()), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(to_hex(&hash_file(HashAlgorithm::Sha256, &abc.path).unwrap()), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(to_hex(&hash_file(HashAlgorithm::Blake3, &empty.path).unwrap()), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(to_hex(&hash_file(HashAlgorithm::Blake3, &abc.path).unwrap()), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    }
+
+    #[test]
+    fn large_files_are_hashed_in_pieces() {
+        let contents: Vec<u8> = (0..(3 * 1024 * 1024 + 17)).map(|i| (i * 31 + i / 7) as u8).collect();
+        let large = file_of("hash-large", &contents);
+        assert_eq!(hash_file(HashAlgorithm::Sha256, &large.path).unwrap(), Sha256::digest(&contents).to_vec());
+        assert_eq!(hash_file(HashAlgorithm::Blake3, &large.path).unwrap(), blake3::hash(&contents).as_bytes().to_vec());
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            assert_eq!(hash_file(algorithm, &large.path).unwrap().len(), algorithm.length());
+        }
+    }
+}
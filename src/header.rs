@@ -0,0 +1,90 @@
+extern crate reed_solomon;
+
+use reed_solomon::Decoder as EccDecoder;
+use reed_solomon::Encoder as EccEncoder;
+
+use std::convert::TryInto;
+
+
+/// amount of bytes the header itself takes up
+const HEADER_DATA_SIZE: usize = 24;
+
+/// amount of ECC bytes protecting the header, it's tiny so we can afford to protect it a lot better than the data blocks
+const HEADER_ECC_SIZE: usize = 24;
+
+/// amount of bytes every data frame starts with when frame headers are used
+pub const FRAME_HEADER_SIZE: usize = HEADER_DATA_SIZE + HEADER_ECC_SIZE;
+
+/// The header at the start of every data frame, it tells the decoder which archive a frame belongs to and where it goes.
+// the header looks like this:
+// - bytes 0-15 are the archive ID, it's the same for every frame of an archive
+// - bytes 16-19 are the sequence number of the data frame, starting at 0 for the frame after the metadata frame
+// - bytes 20-23 are the CRC32 of the frame's content bytes after error correction
+// - bytes 24-47 are the ECC for the header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub archive_id: [u8; 16],
+    pub sequence: u32,
+    pub crc32: u32,
+}
+
+impl FrameHeader {
+    pub fn to_bytes(self) -> [u8; FRAME_HEADER_SIZE] {
+        let mut bytes = [0; FRAME_HEADER_SIZE];
+        bytes[0..=15].copy_from_slice(&self.archive_id);
+        bytes[16..=19].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[20..=23].copy_from_slice(&self.crc32.to_be_bytes());
+        let ecc = EccEncoder::new(HEADER_ECC_SIZE).encode(&bytes[0..HEADER_DATA_SIZE]);
+        bytes[HEADER_DATA_SIZE..].copy_from_slice(ecc.ecc());
+        bytes
+    }
+
+    /// tries to error correct and parse a header, returns None if it's beyond repair
+    pub fn from_bytes(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+        let corrected = EccDecoder::new(HEADER_ECC_SIZE).correct(&bytes[0..FRAME_HEADER_SIZE], None).ok()?;
+        let data = corrected.data();
+        Some(FrameHeader {
+            archive_id: data[0..=15].try_into().unwrap(),
+            sequence: u32::from_be_bytes(data[16..=19].try_into().unwrap()),
+            crc32: u32::from_be_bytes(data[20..=23].try_into().unwrap()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: FrameHeader = FrameHeader { archive_id: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15], sequence: 0x01020304, crc32: 0xdeadbeef };
+
+    #[test]
+    fn round_trip() {
+        for header in [HEADER, FrameHeader { archive_id: [0xff; 16], sequence: u32::MAX, crc32: 0 }] {
+            assert_eq!(FrameHeader::from_bytes(&header.to_bytes()), Some(header));
+        }
+    }
+
+    #[test]
+    fn damage_is_corrected() {
+        let mut bytes = HEADER.to_bytes();
+        // the ECC corrects up to 12 damaged bytes
+        for byte in bytes.iter_mut().step_by(4) {
+            *byte = !*byte;
+        }
+        assert_eq!(FrameHeader::from_bytes(&bytes), Some(HEADER));
+    }
+
+    #[test]
+    fn broken_headers() {
+        let bytes = HEADER.to_bytes();
+        assert_eq!(FrameHeader::from_bytes(&bytes[..FRAME_HEADER_SIZE - 1]), None);
+        let mut bytes = bytes.to_vec();
+        for byte in bytes.iter_mut().take(HEADER_ECC_SIZE) {
+            *byte = !*byte;
+        }
+        assert_ne!(FrameHeader::from_bytes(&bytes), Some(HEADER));
+    }
+}
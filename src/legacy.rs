@@ -0,0 +1,58 @@
+use crate::error::{Result, VideobackupError};
+use crate::metadata::{Metadata, METADATA_BLOCK_SIZE};
+use crate::palette::PaletteFamily;
+
+use std::convert::TryInto;
+
+
+/// the encoding versions before the current one that we can still decode, the newest first. Videos that are already
+/// uploaded somewhere stay decodable forever, so a version never gets removed from here
+pub const LEGACY_VERSIONS: [u16; 1] = [3];
+
+/// size of the metadata in version 3, which is a single block at the very start of the metadata frame
+pub const V3_METADATA_SIZE: usize = METADATA_BLOCK_SIZE;
+
+/// reads the metadata of version 3 from its block (first), which is already error corrected. Version 3 has a fixed
+/// layout and none of the features that came later, the metadata block looks like this:
+/// - bytes 0-1 are the encoding version
+/// - bytes 2-3 are the palette size
+/// - byte 4 is the pixel size, videos from before pixel sizes were a thing always have a 1 there
+/// - bytes 5-12 is the file size
+/// - bytes 13-16 are the CRC32 checksum
+/// - byte 17 is the amount of ECC bytes
+/// - bytes 18-217 are the file name, padded with zeros
+/// - bytes 218-249 are the ECC for the metadata block
+pub fn read_v3(first: &[u8]) -> Option<Result<Metadata>> {
+    let file_name = match String::from_utf8(first[18..=217].to_vec()) {
+        Ok(v) => v.replace('\0', ""),
+        Err(_) => return Some(Err(VideobackupError::CorruptMetadata(String::from("the file name isn't valid UTF-8")))),
+    };
+    let metadata = Metadata {
+        file_name,
+        file_size: u64::from_be_bytes(first[5..=12].try_into().unwrap()),
+        crc32: u32::from_be_bytes(first[13..=16].try_into().unwrap()),
+        ecc_bytes: first[17],
+        palette: PaletteFamily::Color,
+        colors: u16::from_be_bytes(first[2..=3].try_into().unwrap()),
+        pixel_size: first[4],
+        width: 0,
+        height: 0,
+        archive_id: None,
+        interleaved: false,
+        calibration: false,
+        fiducials: false,
+        trailing_copy: false,
+        parity_group: 0,
+        parity_frames: 0,
+        created: None,
+        modified: None,
+        comment: None,
+        hash: None,
+        compression: None,
+        encryption: None,
+        directory: false,
+        part: None,
+    };
+
+    Some(metadata.validate().map(|_| metadata))
+}
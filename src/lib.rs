@@ -0,0 +1,39 @@
+//! videobackup-rs turns any file into a "normal" video and vice versa.
+//!
+//! ```no_run
+//! use videobackup_rs::{DecodeOptions, Decoder, EncodeOptions, Encoder};
+//!
+//! # fn main() -> videobackup_rs::Result<()> {
+//! let video = Encoder::new(EncodeOptions::new().colors(4)).encode("important_document.pdf", "document.mp4")?;
+//! let file = Decoder::new(DecodeOptions::new().output_dir("restored")).decode(&video)?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod archive;
+mod calibration;
+mod codec;
+mod common;
+mod compression;
+mod crypto;
+mod decode;
+mod encode;
+mod error;
+mod ffmpeg;
+mod geometry;
+mod hash;
+mod header;
+mod legacy;
+mod metadata;
+mod palette;
+mod parity;
+
+pub use codec::{PixelFormat, VideoCodec};
+pub use compression::Compression;
+pub use common::ENCODING_VERSION;
+pub use crypto::{X25519Identity, X25519Recipient};
+pub use decode::{DecodeOptions, Decoder};
+pub use encode::{EncodeOptions, Encoder};
+pub use error::{Result, VideobackupError};
+pub use hash::HashAlgorithm;
+pub use palette::PaletteFamily;
@@ -1,15 +1,159 @@
 extern crate clap;
 extern crate num_cpus;
+extern crate rpassword;
 
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, ArgMatches};
 
-mod common;
-mod decode;
-mod encode;
+use videobackup_rs::{DecodeOptions, Decoder, EncodeOptions, Encoder, Result, VideobackupError, X25519Identity, X25519Recipient};
+
+use std::env;
+use std::process;
+use std::str::FromStr;
 
 
 static DISCLAIMER: &str = "IMPORTANT: THIS TOOL COMES WITH NO WARRANTY WHATSOEVER. USE AT YOUR OWN RISK.";
 
+/// scripts can hand us the passphrase through this instead of typing it in
+static PASSPHRASE_VAR: &str = "VIDEOBACKUP_PASSPHRASE";
+
+/// the exit code for every kind of error, so scripts can tell e.g. a corrupted file apart from a missing ffmpeg
+fn exit_code(error: &VideobackupError) -> i32 {
+    match error {
+        VideobackupError::Io(_) => 1,
+        VideobackupError::InvalidParameters(_) => 2,
+        VideobackupError::FfmpegNotFound(_) => 3,
+        VideobackupError::Ffmpeg { .. } => 4,
+        VideobackupError::UnsupportedVersion { .. } | VideobackupError::UnsupportedFeature(_) => 5,
+        VideobackupError::CorruptMetadata(_) | VideobackupError::CorruptArchive(_) => 6,
+        VideobackupError::ChecksumMismatch { .. } | VideobackupError::HashMismatch { .. } => 7,
+        VideobackupError::KeyRequired | VideobackupError::WrongKey => 8,
+    }
+}
+
+/// parses the value of the argument (name), all of our arguments have default values
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<T> {
+    let value = matches.value_of(name).unwrap_or_default();
+    match value.parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(VideobackupError::InvalidParameters(format!("{:?} is not a valid value for {}", value, name))),
+    }
+}
+
+/// parses the value of the optional argument (name)
+fn parse_optional_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>> {
+    if matches.is_present(name) { Ok(Some(parse_arg(matches, name)?)) } else { Ok(None) }
+}
+
+/// a size like 700M or 4.5G in bytes, K, M, G and T are powers of 1024
+fn size_in_bytes(value: &str) -> Option<u64> {
+    let number = value.trim_end_matches(['B', 'b']);
+    let (number, factor) = match number.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    match number.parse::<f64>() {
+        Ok(v) if v >= 0.0 && v.is_finite() => Some((v * factor as f64) as u64),
+        _ => None,
+    }
+}
+
+/// parses the size in the optional argument (name) into bytes
+fn parse_size(matches: &ArgMatches, name: &str) -> Result<Option<u64>> {
+    let value = match matches.value_of(name) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    match size_in_bytes(value) {
+        Some(v) => Ok(Some(v)),
+        None => Err(VideobackupError::InvalidParameters(format!("{:?} is not a valid value for {}", value, name))),
+    }
+}
+
+/// the passphrase from VIDEOBACKUP_PASSPHRASE, otherwise it gets asked for, twice if (confirm) so a typo doesn't lock
+/// anyone out of their own backup
+fn read_passphrase(confirm: bool) -> Result<String> {
+    let passphrase = match env::var(PASSPHRASE_VAR) {
+        Ok(v) => v,
+        Err(_) => {
+            let passphrase = rpassword::prompt_password("Passphrase: ")?;
+            if confirm && rpassword::prompt_password("Repeat the passphrase: ")? != passphrase {
+                return Err(VideobackupError::InvalidParameters(String::from("The passphrases don't match")));
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(VideobackupError::InvalidParameters(String::from("The passphrase can't be empty")));
+    }
+    Ok(passphrase)
+}
+
+fn run_encode(matches: &ArgMatches) -> Result<()> {
+    println!("→ videobackup-rs encoder {}", env!("CARGO_PKG_VERSION"));
+    println!("ℹ {}", DISCLAIMER);
+    let options = EncodeOptions::new()
+            .fps(parse_arg(matches, "fps")?)
+            .width(parse_arg(matches, "width")?)
+            .height(parse_arg(matches, "height")?)
+            .colors(parse_arg(matches, "colors")?)
+            .palette(parse_arg(matches, "palette")?)
+            .pixel_size(parse_arg(matches, "pixel-size")?)
+            .interleave(!matches.is_present("no-interleave"))
+            .calibration(!matches.is_present("no-calibration"))
+            .fiducials(!matches.is_present("no-fiducials"))
+            .ecc_bytes(parse_arg(matches, "bytes")?)
+            .video_codec(parse_arg(matches, "codec")?)
+            .pixel_format(parse_arg(matches, "pixel-format")?)
+            .crf(parse_arg(matches, "crf")?)
+            .parity(parse_arg(matches, "parity-group")?, parse_arg(matches, "parity-frames")?)
+            .compression(match matches.value_of("compress") {
+                Some("none") => None,
+                _ => Some(parse_arg(matches, "compress")?),
+            })
+            .comment(matches.value_of("comment"))
+            .hash(match matches.value_of("hash") {
+                Some("none") => None,
+                _ => Some(parse_arg(matches, "hash")?),
+            })
+            .passphrase(if matches.is_present("encrypt") { Some(read_passphrase(true)?) } else { None })
+            .recipients(matches.values_of("recipient").into_iter().flatten().map(|r| r.parse()).collect::<Result<Vec<X25519Recipient>>>()?)
+            .max_frames(parse_optional_arg(matches, "max-frames")?)
+            .max_duration(parse_optional_arg(matches, "max-duration")?)
+            .max_size(parse_size(matches, "max-size")?)
+            .threads(parse_arg(matches, "threads")?);
+    Encoder::new(options).encode(matches.value_of("INPUT").unwrap(), matches.value_of("OUTPUT").unwrap())?;
+    Ok(())
+}
+
+fn run_decode(matches: &ArgMatches) -> Result<()> {
+    println!("→ videobackup-rs decoder {}", env!("CARGO_PKG_VERSION"));
+    println!("ℹ {}", DISCLAIMER);
+    let options = DecodeOptions::new()
+            .checksum(true)
+            .cluster_palette(matches.is_present("cluster-palette"))
+            .extract(matches.value_of("extract"))
+            .identities(matches.values_of("identity").into_iter().flatten().map(X25519Identity::read_file)
+                    .collect::<Result<Vec<Vec<X25519Identity>>>>()?.into_iter().flatten().collect())
+            .threads(parse_arg(matches, "threads")?);
+    let inputs: Vec<&str> = matches.values_of("INPUT").unwrap().collect();
+    // we only find out whether the video is encrypted once its metadata is read, that's when we ask for the passphrase
+    let result = match env::var(PASSPHRASE_VAR) {
+        Ok(v) => Decoder::new(options.passphrase(Some(v))).decode_parts(&inputs),
+        Err(_) => match Decoder::new(options.clone()).decode_parts(&inputs) {
+            Err(VideobackupError::KeyRequired) => {
+                let passphrase = read_passphrase(false).map_err(|_| VideobackupError::KeyRequired)?;
+                Decoder::new(options.passphrase(Some(passphrase))).decode_parts(&inputs)
+            },
+            result => result,
+        },
+    };
+    result?;
+    Ok(())
+}
+
 fn main() {
     let cpus = num_cpus::get().to_string();
 
@@ -20,9 +164,9 @@ fn main() {
             .subcommand(App::new("encode")
                     .version(env!("CARGO_PKG_VERSION"))
                     .author("ManicRobot")
-                    .about("Encodes a file into a video file")
+                    .about("Encodes a file or directory into a video file")
                     .arg(Arg::with_name("INPUT")
-                            .help("The file to be turned into a video")
+                            .help("The file or directory to be turned into a video")
                             .index(1)
                             .takes_value(true)
                             .multiple(false)
@@ -60,9 +204,31 @@ fn main() {
                             .aliases(&["colours"])
                             .help("Amount of colors used. Less colors will take longer for encoding/decoding and make the file larger but the video will be more resistant against compression.")
                             .multiple(false)
-                            .possible_values(&["2", "4"])
+                            .possible_values(&["2", "4", "8", "16", "32", "64", "128", "256"])
                             .default_value("2")
                             .takes_value(true))
+                    .arg(Arg::with_name("palette")
+                            .long("palette")
+                            .help("Which kind of palette to use: color or grayscale. Grayscale palettes only have up to 8 shades of gray but survive platforms that reduce the color resolution.")
+                            .multiple(false)
+                            .default_value("color")
+                            .takes_value(true))
+                    .arg(Arg::with_name("pixel-size")
+                            .long("pixel-size")
+                            .short("p")
+                            .help("Width and height of the squares each color gets drawn as. Larger squares will make the file larger but the video will survive heavier compression.")
+                            .multiple(false)
+                            .default_value("1")
+                            .takes_value(true))
+                    .arg(Arg::with_name("no-interleave")
+                            .long("no-interleave")
+                            .help("Don't spread the bytes of each block across the whole frame. Interleaving makes sure compression artifacts in one spot don't overwhelm the ECC of a single block."))
+                    .arg(Arg::with_name("no-calibration")
+                            .long("no-calibration")
+                            .help("Don't add a calibration frame. The decoder uses it to learn how the colors look after the video got re-encoded."))
+                    .arg(Arg::with_name("no-fiducials")
+                            .long("no-fiducials")
+                            .help("Don't draw fiducials around the cells. The decoder uses them to find the cells when the video got scaled, letterboxed or cropped."))
                     .arg(Arg::with_name("bytes")
                             .long("ecc-bytes")
                             .short("e")
@@ -70,20 +236,81 @@ fn main() {
                             .multiple(false)
                             .default_value("16")
                             .takes_value(true))
-                    // TODO: Reimplement custom codec
-                    //.arg(Arg::with_name("codec")
-                    //        .long("video-codec")
-                    //        .short("vc")
-                    //        .help("Tells ffmpeg which video encoder to use.")
-                    //        .multiple(false)
-                    //        .default_value("libx264")
-                    //        .takes_value(true))
+                    .arg(Arg::with_name("codec")
+                            .long("video-codec")
+                            .help("Which video codec to use: x264, x265, vp9, av1 or ffv1 (lossless). The container of OUTPUT has to be able to hold it.")
+                            .multiple(false)
+                            .default_value("x264")
+                            .takes_value(true))
+                    .arg(Arg::with_name("pixel-format")
+                            .long("pixel-format")
+                            .help("How the colors get stored: yuv444p keeps them at full resolution, yuv420p at a quarter of it. x264 and x265 need their High 4:4:4 profiles for yuv444p, which a lot of players and upload platforms reject or re-encode badly, use yuv420p with a grayscale palette for those.")
+                            .multiple(false)
+                            .default_value("yuv444p")
+                            .takes_value(true))
                     .arg(Arg::with_name("crf")
                             .long("crf")
-                            .help("Quality of the video (constant rate factor). Lower values will increase quality (therefore less compression artifacts) and file size. Might not work with every video codec.")
+                            .help("Quality of the video (constant rate factor). Lower values will increase quality (therefore less compression artifacts) and file size. The allowed range depends on the video codec.")
                             .multiple(false)
                             .default_value("24")
                             .takes_value(true))
+                    .arg(Arg::with_name("parity-frames")
+                            .long("parity-frames")
+                            .help("Amount of parity frames added after every parity group. Each parity frame lets the decoder reconstruct one lost or damaged frame of its group, 0 turns them off.")
+                            .multiple(false)
+                            .default_value("0")
+                            .takes_value(true))
+                    .arg(Arg::with_name("parity-group")
+                            .long("parity-group")
+                            .help("Amount of data frames in a parity group")
+                            .multiple(false)
+                            .default_value("16")
+                            .takes_value(true))
+                    .arg(Arg::with_name("hash")
+                            .long("hash")
+                            .help("Which cryptographic hash of the file to store next to the CRC32: sha256, blake3 or none. The decoder checks it and shows it, so you can compare it with your own records.")
+                            .multiple(false)
+                            .default_value("sha256")
+                            .takes_value(true))
+                    .arg(Arg::with_name("compress")
+                            .long("compress")
+                            .help("Compress the file before encoding it: zstd, xz or none. The decoder decompresses it on its own.")
+                            .multiple(false)
+                            .default_value("none")
+                            .takes_value(true))
+                    .arg(Arg::with_name("comment")
+                            .long("comment")
+                            .help("A comment that gets stored in the video and shown when decoding it")
+                            .multiple(false)
+                            .takes_value(true))
+                    .arg(Arg::with_name("encrypt")
+                            .long("encrypt")
+                            .help("Encrypt the file, its name and everything else in the metadata that gives anything away about it with a passphrase. It's asked for, unless it's in the VIDEOBACKUP_PASSPHRASE environment variable."))
+                    .arg(Arg::with_name("recipient")
+                            .long("recipient")
+                            .short("r")
+                            .value_name("age1...")
+                            .help("Encrypt the file for the holder of the identity of this X25519 public key, can be given several times. Keys made by age-keygen work, encoding doesn't need the identity.")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .takes_value(true))
+                    .arg(Arg::with_name("max-frames")
+                            .long("max-frames")
+                            .help("Split the video into numbered parts with at most this many frames each, like backup.part1.mp4, backup.part2.mp4 and so on")
+                            .multiple(false)
+                            .takes_value(true))
+                    .arg(Arg::with_name("max-duration")
+                            .long("max-duration")
+                            .value_name("seconds")
+                            .help("Split the video into numbered parts that are at most this many seconds long each")
+                            .multiple(false)
+                            .takes_value(true))
+                    .arg(Arg::with_name("max-size")
+                            .long("max-size")
+                            .value_name("size")
+                            .help("Split the video into numbered parts that are at most this large each, e.g. 700M or 4.5G. A part that turns out too large gets encoded again with fewer frames.")
+                            .multiple(false)
+                            .takes_value(true))
                     .arg(Arg::with_name("threads")
                             .long("threads")
                             .short("t")
@@ -96,10 +323,10 @@ fn main() {
                     .author("ManicRobot")
                     .about("Decodes a video file into the original file")
                     .arg(Arg::with_name("INPUT")
-                            .help("The file to be turned into a video")
+                            .help("The video to be turned back into a file, or all the parts of a split video in any order")
                             .index(1)
                             .takes_value(true)
-                            .multiple(false)
+                            .multiple(true)
                             .required(true))
                     .arg(Arg::with_name("threads")
                             .long("threads")
@@ -107,27 +334,57 @@ fn main() {
                             .help("How many threads to use")
                             .multiple(false)
                             .default_value(&cpus)
-                            .takes_value(true)))
+                            .takes_value(true))
+                    .arg(Arg::with_name("identity")
+                            .long("identity")
+                            .short("i")
+                            .value_name("file")
+                            .help("A file with X25519 identities (AGE-SECRET-KEY-1...) to decrypt the video with, one per line, can be given several times")
+                            .multiple(true)
+                            .number_of_values(1)
+                            .takes_value(true))
+                    .arg(Arg::with_name("extract")
+                            .long("extract")
+                            .value_name("path")
+                            .help("Only extract this file or directory out of a video of a directory. The path is relative to the directory, the extracted file keeps it.")
+                            .multiple(false)
+                            .takes_value(true))
+                    .arg(Arg::with_name("cluster-palette")
+                            .long("cluster-palette")
+                            .help("Learn the palette of every frame with k-means if the video has no calibration frame. Helps with old videos whose colors shifted when they got re-encoded.")))
             .get_matches();
     
-    if let Some(ref matches) = matches.subcommand_matches("encode") {
-        println!("→ videobackup-rs encoder {}", env!("CARGO_PKG_VERSION"));
-        println!("ℹ {}", DISCLAIMER);
-        encode::encode(matches.value_of("INPUT").unwrap(),
-                       matches.value_of("OUTPUT").unwrap(),
-                       matches.value_of("fps").unwrap().parse::<u16>().unwrap(),
-                       matches.value_of("width").unwrap().parse::<usize>().unwrap(),
-                       matches.value_of("height").unwrap().parse::<usize>().unwrap(),
-                       matches.value_of("colors").unwrap().parse::<u16>().unwrap(),
-                       matches.value_of("bytes").unwrap().parse::<u8>().unwrap(),
-                       String::from("libx264"),
-                       matches.value_of("crf").unwrap().parse::<u16>().unwrap(),
-                       matches.value_of("threads").unwrap().parse::<usize>().unwrap());
-    } else if let Some(ref matches) = matches.subcommand_matches("decode") {
-        println!("→ videobackup-rs decoder {}", env!("CARGO_PKG_VERSION"));
-        println!("ℹ {}", DISCLAIMER);
-        decode::decode(matches.value_of("INPUT").unwrap(), true, matches.value_of("threads").unwrap().parse::<usize>().unwrap())
+    let result = if let Some(matches) = matches.subcommand_matches("encode") {
+        run_encode(matches)
+    } else if let Some(matches) = matches.subcommand_matches("decode") {
+        run_decode(matches)
     } else {
-        println!("⚠ Invalid subcommand! Use this command with --help for explanation!")
+        println!("⚠ Invalid subcommand! Use this command with --help for explanation!");
+        Err(VideobackupError::InvalidParameters(String::from("no subcommand given")))
+    };
+
+    if let Err(e) = result {
+        eprintln!("✗ {}", e);
+        process::exit(exit_code(&e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        for (value, bytes) in [("0", 0), ("1000", 1000), ("1000B", 1000), ("2K", 2048), ("2kb", 2048), ("700M", 700 << 20),
+                               ("4.5G", 9 << 29), ("1T", 1 << 40), ("0.5k", 512)] {
+            assert_eq!(size_in_bytes(value), Some(bytes), "{}", value);
+        }
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        for value in ["", "B", "M", "-1M", "NaN", "inf", "1X", "1 G", "1MM"] {
+            assert_eq!(size_in_bytes(value), None, "{}", value);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,737 @@
+extern crate reed_solomon;
+
+use crate::common::{FrameLayout, BLOCK_SIZE, ENCODING_VERSION, MAX_PIXEL_SIZE};
+use crate::compression::Compression;
+use crate::crypto::{open_metadata, seal_metadata, Encryption, KdfParams, Key, Recipient};
+use crate::error::{Result, VideobackupError};
+use crate::hash::HashAlgorithm;
+use crate::legacy;
+use crate::palette::PaletteFamily;
+
+use reed_solomon::Decoder as EccDecoder;
+use reed_solomon::Encoder as EccEncoder;
+
+use std::convert::TryInto;
+use std::path::Path;
+
+
+/// size of a metadata block including its ECC, the metadata takes up as many of them as it needs
+pub const METADATA_BLOCK_SIZE: usize = 250;
+
+/// amount of ECC bytes protecting every metadata block
+const METADATA_ECC_SIZE: usize = 32;
+
+const METADATA_BLOCK_DATA: usize = METADATA_BLOCK_SIZE - METADATA_ECC_SIZE;
+
+/// the most blocks the metadata may take up
+pub const MAX_METADATA_BLOCKS: usize = 8;
+
+/// the longest file name we store, most file systems don't allow longer ones anyway
+pub const MAX_FILE_NAME_LENGTH: usize = 255;
+
+// the metadata is a stream of bytes that looks like this:
+// - bytes 0-1 are the encoding version, every version has it there so the decoder can tell them apart
+// - bytes 2-3 are the length of the fields
+// - then come the fields, every field is a tag byte, the length of its value as 2 bytes and the value itself
+// the stream is padded with zeros to fill up its last block and every block is followed by its ECC. Fields whose tag
+// has the highest bit set are critical: a decoder that doesn't know them can't decode the video, all other fields it
+// doesn't know are skipped, so newer versions can add fields without breaking older ones
+const CRITICAL: u8 = 0b10000000;
+
+const TAG_FILE_NAME: u8 = 0x01;
+const TAG_FILE_SIZE: u8 = 0x02;
+const TAG_CRC32: u8 = 0x03;
+const TAG_ECC_BYTES: u8 = 0x04;
+/// the palette family and the amount of colors
+const TAG_PALETTE: u8 = 0x05;
+const TAG_CELL_SIZE: u8 = 0x06;
+/// width and height of the video as it was encoded
+const TAG_GEOMETRY: u8 = 0x07;
+/// the ID in the frame header every data frame starts with
+const TAG_ARCHIVE_ID: u8 = 0x08;
+const TAG_FLAGS: u8 = 0x09;
+/// data frames per parity group and the parity frames following each group
+const TAG_PARITY: u8 = 0x0a;
+/// when the video was encoded, in seconds since the unix epoch
+const TAG_CREATED: u8 = 0x0b;
+/// when the file was modified for the last time, in seconds since the unix epoch
+const TAG_MODIFIED: u8 = 0x0c;
+const TAG_COMMENT: u8 = 0x0d;
+/// the hash algorithm as a byte and the hash of the file
+const TAG_HASH: u8 = 0x0e;
+/// the compression algorithm as a byte and the size of the file before it got compressed
+const TAG_COMPRESSION: u8 = CRITICAL | 0x0f;
+/// the size of the encrypted chunks and the sealed part of the metadata
+const TAG_ENCRYPTION: u8 = CRITICAL | 0x10;
+/// the salt and parameters of the key derivation and the file key encrypted with the derived key, there's one of
+/// these for every passphrase
+const TAG_PASSPHRASE: u8 = 0x11;
+/// the public half of the throwaway X25519 key and the file key encrypted with the key it agreed on with the
+/// recipient, there's one of these for every X25519 recipient
+const TAG_X25519: u8 = 0x12;
+/// the file is a directory packed into an archive, the value is the format of the archive
+const TAG_DIRECTORY: u8 = CRITICAL | 0x13;
+/// the video is a part of a larger one, the value is the index of the part, the sequence number of its first data
+/// frame and how many data frames it has
+const TAG_PART: u8 = CRITICAL | 0x14;
+
+/// the only archive format so far, see archive.rs
+const ARCHIVE_FORMAT: u8 = 0;
+
+/// the fields every metadata has
+const REQUIRED_FIELDS: [(u8, &str); 7] = [(TAG_FILE_NAME, "file name"), (TAG_FILE_SIZE, "file size"), (TAG_CRC32, "CRC32"),
+                                          (TAG_ECC_BYTES, "amount of ECC bytes"), (TAG_PALETTE, "palette"), (TAG_CELL_SIZE, "pixel size"),
+                                          (TAG_GEOMETRY, "geometry")];
+
+/// the fields that give away something about the file, if it's encrypted they only go into the sealed part of the metadata
+const SEALED_TAGS: [u8; 6] = [TAG_FILE_NAME, TAG_CRC32, TAG_MODIFIED, TAG_COMMENT, TAG_HASH, TAG_COMPRESSION];
+
+const FLAG_INTERLEAVED: u8 = 0b00000001;
+const FLAG_CALIBRATION: u8 = 0b00000010;
+const FLAG_FIDUCIALS: u8 = 0b00000100;
+const FLAG_TRAILING_COPY: u8 = 0b00001000;
+
+/// Everything the decoder needs to know about a video and the file in it, it's stored in the metadata frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_name: String,
+    /// size of the data in the video, if the file is compressed that's its compressed size
+    pub file_size: u64,
+    pub crc32: u32,
+    pub ecc_bytes: u8,
+    pub palette: PaletteFamily,
+    pub colors: u16,
+    pub pixel_size: u8,
+    /// size of the video as it was encoded
+    pub width: u32,
+    pub height: u32,
+    /// the ID in the frame headers, None if the data frames don't have any
+    pub archive_id: Option<[u8; 16]>,
+    pub interleaved: bool,
+    /// whether a calibration frame follows the metadata frame
+    pub calibration: bool,
+    pub fiducials: bool,
+    /// whether the last frame of the video is a copy of the metadata frame
+    pub trailing_copy: bool,
+    /// data frames per parity group
+    pub parity_group: u8,
+    /// parity frames following each parity group, 0 if there are none
+    pub parity_frames: u8,
+    /// when the video was encoded, in seconds since the unix epoch
+    pub created: Option<u64>,
+    /// when the file was modified for the last time, in seconds since the unix epoch
+    pub modified: Option<u64>,
+    pub comment: Option<String>,
+    /// a cryptographic hash of the file
+    pub hash: Option<(HashAlgorithm, Vec<u8>)>,
+    /// the algorithm the file is compressed with and its size before it got compressed
+    pub compression: Option<(Compression, u64)>,
+    pub encryption: Option<Encryption>,
+    /// whether the file is an archive of a directory that has to be unpacked after decoding
+    pub directory: bool,
+    /// which part of the whole this video is, if it got split into several
+    pub part: Option<Part>,
+}
+
+/// Where a part of a video that got split into several belongs, the parts share everything else in their metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Part {
+    /// counts from 0, people get to see it counting from 1
+    pub index: u16,
+    /// the sequence number of the first data frame in the part
+    pub first_frame: u64,
+    pub data_frames: u64,
+}
+
+fn push_field(fields: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    fields.push(tag);
+    fields.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    fields.extend_from_slice(value);
+}
+
+/// the value of a field that always has a length of N bytes
+fn fixed<const N: usize>(value: &[u8], name: &str) -> Result<[u8; N]> {
+    match value.try_into() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(VideobackupError::CorruptMetadata(format!("the {} has {} bytes instead of {}", name, value.len(), N))),
+    }
+}
+
+fn text(value: &[u8], name: &str) -> Result<String> {
+    match String::from_utf8(value.to_vec()) {
+        Ok(v) => Ok(v),
+        Err(_) => Err(VideobackupError::CorruptMetadata(format!("the {} isn't valid UTF-8", name))),
+    }
+}
+
+/// makes sure every required field (which) applies to is among (tags)
+fn check_required<F: Fn(u8) -> bool>(tags: &[u8], which: F) -> Result<()> {
+    for (tag, name) in REQUIRED_FIELDS.iter().filter(|(t, _)| which(*t)) {
+        if !tags.contains(tag) {
+            return Err(VideobackupError::CorruptMetadata(format!("the {} is missing", name)));
+        }
+    }
+    Ok(())
+}
+
+impl Metadata {
+    /// the layout of the data frames
+    pub fn layout(&self) -> FrameLayout {
+        FrameLayout { width: self.width as usize, height: self.height as usize, colors: self.colors, palette: self.palette,
+                      pixel_size: self.pixel_size, interleaved: self.interleaved, fiducials: self.fiducials }
+    }
+
+    /// the metadata of no file at all, the fields fill it in
+    pub(crate) fn empty() -> Metadata {
+        Metadata {
+            file_name: String::new(), file_size: 0, crc32: 0, ecc_bytes: 0, palette: PaletteFamily::Color, colors: 0, pixel_size: 0,
+            width: 0, height: 0, archive_id: None, interleaved: false, calibration: false, fiducials: false, trailing_copy: false,
+            parity_group: 0, parity_frames: 0, created: None, modified: None, comment: None, hash: None, compression: None,
+            encryption: None, directory: false, part: None,
+        }
+    }
+
+    /// the fields of the sealed part of the metadata if (sealed), otherwise the ones that are out in the open, which are
+    /// all of them if the file isn't encrypted
+    fn fields(&self, sealed: bool) -> Vec<u8> {
+        let mut fields = Vec::new();
+        let mut push = |tag: u8, value: &[u8]| {
+            if (self.encryption.is_some() && SEALED_TAGS.contains(&tag)) == sealed {
+                push_field(&mut fields, tag, value);
+            }
+        };
+        push(TAG_FILE_NAME, self.file_name.as_bytes());
+        push(TAG_FILE_SIZE, &self.file_size.to_be_bytes());
+        push(TAG_CRC32, &self.crc32.to_be_bytes());
+        push(TAG_ECC_BYTES, &[self.ecc_bytes]);
+        let mut palette = vec![self.palette.to_byte()];
+        palette.extend_from_slice(&self.colors.to_be_bytes());
+        push(TAG_PALETTE, &palette);
+        push(TAG_CELL_SIZE, &[self.pixel_size]);
+        let mut geometry = self.width.to_be_bytes().to_vec();
+        geometry.extend_from_slice(&self.height.to_be_bytes());
+        push(TAG_GEOMETRY, &geometry);
+        if let Some(archive_id) = self.archive_id {
+            push(TAG_ARCHIVE_ID, &archive_id);
+        }
+        let flags = if self.interleaved { FLAG_INTERLEAVED } else { 0 } | if self.calibration { FLAG_CALIBRATION } else { 0 }
+                  | if self.fiducials { FLAG_FIDUCIALS } else { 0 } | if self.trailing_copy { FLAG_TRAILING_COPY } else { 0 };
+        push(TAG_FLAGS, &[flags]);
+        if self.parity_frames > 0 {
+            push(TAG_PARITY, &[self.parity_group, self.parity_frames]);
+        }
+        if let Some(created) = self.created {
+            push(TAG_CREATED, &created.to_be_bytes());
+        }
+        if let Some(modified) = self.modified {
+            push(TAG_MODIFIED, &modified.to_be_bytes());
+        }
+        if let Some(comment) = &self.comment {
+            push(TAG_COMMENT, comment.as_bytes());
+        }
+        if let Some((algorithm, hash)) = &self.hash {
+            let mut value = vec![algorithm.to_byte()];
+            value.extend_from_slice(hash);
+            push(TAG_HASH, &value);
+        }
+        if let Some((compression, size)) = self.compression {
+            let mut value = vec![compression.to_byte()];
+            value.extend_from_slice(&size.to_be_bytes());
+            push(TAG_COMPRESSION, &value);
+        }
+        if self.directory {
+            push(TAG_DIRECTORY, &[ARCHIVE_FORMAT]);
+        }
+        if let Some(part) = self.part {
+            let mut value = part.index.to_be_bytes().to_vec();
+            value.extend_from_slice(&part.first_frame.to_be_bytes());
+            value.extend_from_slice(&part.data_frames.to_be_bytes());
+            push(TAG_PART, &value);
+        }
+        if let Some(encryption) = &self.encryption {
+            let mut value = encryption.chunk_size.to_be_bytes().to_vec();
+            value.extend_from_slice(&encryption.sealed);
+            push(TAG_ENCRYPTION, &value);
+            for recipient in encryption.recipients.iter() {
+                match recipient {
+                    Recipient::Passphrase { salt, kdf, wrapped_key } => {
+                        let mut value = salt.to_vec();
+                        value.extend_from_slice(&kdf.memory.to_be_bytes());
+                        value.extend_from_slice(&kdf.iterations.to_be_bytes());
+                        value.push(kdf.parallelism);
+                        value.extend_from_slice(wrapped_key);
+                        push(TAG_PASSPHRASE, &value);
+                    },
+                    Recipient::X25519 { share, wrapped_key } => {
+                        let mut value = share.to_vec();
+                        value.extend_from_slice(wrapped_key);
+                        push(TAG_X25519, &value);
+                    },
+                }
+            }
+        }
+        fields
+    }
+
+    /// fills in the fields from (fields), returns the tags of all of them
+    fn apply_fields(&mut self, fields: &[u8]) -> Result<Vec<u8>> {
+        let mut tags = Vec::new();
+        let mut i = 0;
+        while i < fields.len() {
+            if i + 3 > fields.len() {
+                return Err(VideobackupError::CorruptMetadata(String::from("the last field is cut off")));
+            }
+            let tag = fields[i];
+            let length = u16::from_be_bytes(fields[(i + 1)..=(i + 2)].try_into().unwrap()) as usize;
+            let value = match fields.get((i + 3)..(i + 3 + length)) {
+                Some(v) => v,
+                None => return Err(VideobackupError::CorruptMetadata(String::from("the last field is cut off"))),
+            };
+            i += 3 + length;
+            tags.push(tag);
+            match tag {
+                TAG_FILE_NAME => self.file_name = text(value, "file name")?,
+                TAG_FILE_SIZE => self.file_size = u64::from_be_bytes(fixed(value, "file size")?),
+                TAG_CRC32 => self.crc32 = u32::from_be_bytes(fixed(value, "CRC32")?),
+                TAG_ECC_BYTES => self.ecc_bytes = fixed::<1>(value, "amount of ECC bytes")?[0],
+                TAG_PALETTE => {
+                    let value: [u8; 3] = fixed(value, "palette")?;
+                    self.palette = match PaletteFamily::from_byte(value[0]) {
+                        Some(v) => v,
+                        None => return Err(VideobackupError::CorruptMetadata(format!("{} is not a known palette", value[0]))),
+                    };
+                    self.colors = u16::from_be_bytes([value[1], value[2]]);
+                },
+                TAG_CELL_SIZE => self.pixel_size = fixed::<1>(value, "pixel size")?[0],
+                TAG_GEOMETRY => {
+                    let value: [u8; 8] = fixed(value, "geometry")?;
+                    self.width = u32::from_be_bytes(value[0..=3].try_into().unwrap());
+                    self.height = u32::from_be_bytes(value[4..=7].try_into().unwrap());
+                },
+                TAG_ARCHIVE_ID => self.archive_id = Some(fixed(value, "archive ID")?),
+                TAG_FLAGS => {
+                    let flags = fixed::<1>(value, "flags")?[0];
+                    self.interleaved = flags & FLAG_INTERLEAVED != 0;
+                    self.calibration = flags & FLAG_CALIBRATION != 0;
+                    self.fiducials = flags & FLAG_FIDUCIALS != 0;
+                    self.trailing_copy = flags & FLAG_TRAILING_COPY != 0;
+                },
+                TAG_PARITY => {
+                    let value: [u8; 2] = fixed(value, "parity")?;
+                    self.parity_group = value[0];
+                    self.parity_frames = value[1];
+                },
+                TAG_CREATED => self.created = Some(u64::from_be_bytes(fixed(value, "creation time")?)),
+                TAG_MODIFIED => self.modified = Some(u64::from_be_bytes(fixed(value, "modification time")?)),
+                TAG_COMMENT => self.comment = Some(text(value, "comment")?),
+                TAG_HASH => {
+                    // a hash of an algorithm from a newer version can't be checked, but the file can still be decoded
+                    if let Some(algorithm) = value.first().and_then(|b| HashAlgorithm::from_byte(*b)) {
+                        if value.len() != 1 + algorithm.length() {
+                            return Err(VideobackupError::CorruptMetadata(format!("the {} hash has {} bytes instead of {}", algorithm, value.len() - 1, algorithm.length())));
+                        }
+                        self.hash = Some((algorithm, value[1..].to_vec()));
+                    }
+                },
+                TAG_COMPRESSION => {
+                    let value: [u8; 9] = fixed(value, "compression")?;
+                    let compression = match Compression::from_byte(value[0]) {
+                        Some(v) => v,
+                        None => return Err(VideobackupError::UnsupportedFeature(format!("compression algorithm {}", value[0]))),
+                    };
+                    self.compression = Some((compression, u64::from_be_bytes(value[1..=8].try_into().unwrap())));
+                },
+                TAG_DIRECTORY => {
+                    let format = fixed::<1>(value, "archive format")?[0];
+                    if format != ARCHIVE_FORMAT {
+                        return Err(VideobackupError::UnsupportedFeature(format!("archive format {}", format)));
+                    }
+                    self.directory = true;
+                },
+                TAG_PART => {
+                    let value: [u8; 18] = fixed(value, "part")?;
+                    self.part = Some(Part {
+                        index: u16::from_be_bytes([value[0], value[1]]),
+                        first_frame: u64::from_be_bytes(value[2..=9].try_into().unwrap()),
+                        data_frames: u64::from_be_bytes(value[10..=17].try_into().unwrap()),
+                    });
+                },
+                TAG_ENCRYPTION => {
+                    if value.len() < 4 {
+                        return Err(VideobackupError::CorruptMetadata(String::from("the encryption field is cut off")));
+                    }
+                    self.encryption = Some(Encryption { chunk_size: u32::from_be_bytes(value[0..=3].try_into().unwrap()), recipients: Vec::new(), sealed: value[4..].to_vec() });
+                },
+                TAG_PASSPHRASE | TAG_X25519 => {
+                    let recipient = if tag == TAG_PASSPHRASE {
+                        if value.len() < 25 {
+                            return Err(VideobackupError::CorruptMetadata(String::from("the passphrase field is cut off")));
+                        }
+                        let kdf = KdfParams { memory: u32::from_be_bytes(value[16..=19].try_into().unwrap()), iterations: u32::from_be_bytes(value[20..=23].try_into().unwrap()), parallelism: value[24] };
+                        Recipient::Passphrase { salt: value[0..=15].try_into().unwrap(), kdf, wrapped_key: value[25..].to_vec() }
+                    } else {
+                        if value.len() < 32 {
+                            return Err(VideobackupError::CorruptMetadata(String::from("the X25519 recipient field is cut off")));
+                        }
+                        Recipient::X25519 { share: value[0..=31].try_into().unwrap(), wrapped_key: value[32..].to_vec() }
+                    };
+                    match &mut self.encryption {
+                        Some(v) => v.recipients.push(recipient),
+                        None => return Err(VideobackupError::CorruptMetadata(String::from("a recipient came before the encryption field"))),
+                    }
+                },
+                _ if tag & CRITICAL != 0 => return Err(VideobackupError::UnsupportedFeature(format!("a metadata field with the tag {:#04x}", tag))),
+                // a field from a newer version we can do without
+                _ => {},
+            }
+        }
+        Ok(tags)
+    }
+
+    fn from_fields(fields: &[u8]) -> Result<Metadata> {
+        let mut metadata = Metadata::empty();
+        let tags = metadata.apply_fields(fields)?;
+        // the sealed fields can only be checked once the metadata got unsealed
+        if metadata.encryption.is_some() {
+            check_required(&tags, |t| !SEALED_TAGS.contains(&t))?;
+        } else {
+            check_required(&tags, |_| true)?;
+            metadata.validate()?;
+        }
+        Ok(metadata)
+    }
+
+    /// the fields that don't get sealed but mustn't be changed either, they go into the authentication of the sealed part
+    fn sealed_aad(&self) -> Vec<u8> {
+        let mut aad = self.file_size.to_be_bytes().to_vec();
+        if let Some(encryption) = &self.encryption {
+            aad.extend_from_slice(&encryption.chunk_size.to_be_bytes());
+        }
+        aad
+    }
+
+    /// encrypts the fields that would give away anything about the file with (key), they only end up in the sealed part
+    /// of the metadata from now on
+    pub fn seal(&mut self, key: &Key) {
+        let fields = self.fields(true);
+        let aad = self.sealed_aad();
+        if let Some(encryption) = &mut self.encryption {
+            encryption.sealed = seal_metadata(key, &aad, &fields);
+        }
+    }
+
+    /// decrypts the sealed part of the metadata with (key) and fills in its fields
+    pub fn unseal(&mut self, key: &Key) -> Result<()> {
+        let sealed = match &self.encryption {
+            Some(v) => &v.sealed,
+            None => return Ok(()),
+        };
+        let fields = match open_metadata(key, &self.sealed_aad(), sealed) {
+            Some(v) => v,
+            None => return Err(VideobackupError::CorruptMetadata(String::from("the encrypted part of the metadata was tampered with"))),
+        };
+        let tags = self.apply_fields(&fields)?;
+        check_required(&tags, |t| SEALED_TAGS.contains(&t))?;
+        self.validate()
+    }
+
+    /// checks whether the metadata makes sense before we trust it with anything
+    pub fn validate(&self) -> Result<()> {
+        if !self.palette.supports(self.colors) || self.ecc_bytes > BLOCK_SIZE / 2 {
+            return Err(VideobackupError::CorruptMetadata(format!("{} colors of a {} palette and {} ECC bytes are not valid", self.colors, self.palette, self.ecc_bytes)));
+        }
+        if self.pixel_size == 0 || self.pixel_size > MAX_PIXEL_SIZE {
+            return Err(VideobackupError::CorruptMetadata(format!("a pixel size of {} is not valid", self.pixel_size)));
+        }
+        if self.parity_frames > 0 && (self.archive_id.is_none() || self.parity_group == 0) {
+            return Err(VideobackupError::CorruptMetadata(String::from("parity frames need frame headers and at least 1 data frame per group")));
+        }
+        if self.encryption.as_ref().map(|e| e.chunk_size == 0).unwrap_or(false) {
+            return Err(VideobackupError::CorruptMetadata(String::from("encrypted chunks can't be empty")));
+        }
+        if self.interleaved && self.archive_id.is_none() {
+            return Err(VideobackupError::CorruptMetadata(String::from("interleaved blocks need frame headers")));
+        }
+        // don't let a metadata frame trick us into writing outside of the output directory
+        let file_name = &self.file_name;
+        if file_name.is_empty() || file_name.len() > MAX_FILE_NAME_LENGTH || Path::new(file_name).file_name().map(|n| n.to_string_lossy() != *file_name).unwrap_or(true) {
+            return Err(VideobackupError::CorruptMetadata(format!("{:?} is not a valid file name", file_name)));
+        }
+        Ok(())
+    }
+
+    /// the metadata as it's drawn into the metadata frame, error correction included
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let fields = self.fields(false);
+        let mut stream: Vec<u8> = Vec::with_capacity(4 + fields.len());
+        stream.extend_from_slice(&ENCODING_VERSION.to_be_bytes());
+        stream.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        stream.extend_from_slice(&fields);
+        let blocks = stream.len().div_ceil(METADATA_BLOCK_DATA);
+        if blocks > MAX_METADATA_BLOCKS {
+            return Err(VideobackupError::InvalidParameters(format!("The metadata can't be longer than {} bytes, use a shorter comment!", MAX_METADATA_BLOCKS * METADATA_BLOCK_DATA - 4)));
+        }
+        stream.resize(blocks * METADATA_BLOCK_DATA, 0);
+
+        let ecc_encoder = EccEncoder::new(METADATA_ECC_SIZE);
+        let mut bytes: Vec<u8> = Vec::with_capacity(blocks * METADATA_BLOCK_SIZE);
+        for block in stream.chunks(METADATA_BLOCK_DATA) {
+            bytes.extend_from_slice(block);
+            bytes.extend_from_slice(ecc_encoder.encode(block).ecc());
+        }
+        Ok(bytes)
+    }
+
+    /// how many bytes a copy of the metadata of (version) may take up. The copies are spread over the metadata frame
+    /// according to their size, so the decoder has to look for copies of every size it might have
+    pub fn copy_sizes(version: u16) -> Vec<usize> {
+        match version {
+            3 => vec![legacy::V3_METADATA_SIZE],
+            _ => (1..=MAX_METADATA_BLOCKS).map(|b| b * METADATA_BLOCK_SIZE).collect(),
+        }
+    }
+
+    /// error corrects and parses a copy of the metadata of (version) that starts at the start of (copy), returns None if
+    /// the copy is beyond repair, if there's no copy at all or if it's a copy of another version, which is somewhere else
+    pub fn read(copy: &[u8], version: u16) -> Option<Result<Metadata>> {
+        if copy.len() < METADATA_BLOCK_SIZE {
+            return None;
+        }
+        // every version starts with a block of the same size that begins with the encoding version
+        let ecc_decoder = EccDecoder::new(METADATA_ECC_SIZE);
+        let first = ecc_decoder.correct(&copy[0..METADATA_BLOCK_SIZE], None).ok()?;
+        let found = u16::from_be_bytes(first.data()[0..=1].try_into().unwrap());
+        // only zeros, that's what's there when a video has less copies than we're looking for
+        if found == 0 {
+            return None;
+        }
+        if !versions().contains(&found) {
+            return Some(Err(VideobackupError::UnsupportedVersion { found, supported: ENCODING_VERSION }));
+        }
+        if found != version {
+            return None;
+        }
+        match version {
+            3 => legacy::read_v3(first.data()),
+            _ => Metadata::read_v4(first.data(), copy),
+        }
+    }
+
+    /// reads the fields that follow the first block (first), which is already error corrected
+    fn read_v4(first: &[u8], copy: &[u8]) -> Option<Result<Metadata>> {
+        let length = u16::from_be_bytes(first[2..=3].try_into().unwrap()) as usize;
+        let blocks = (4 + length).div_ceil(METADATA_BLOCK_DATA);
+        if blocks > MAX_METADATA_BLOCKS {
+            return Some(Err(VideobackupError::CorruptMetadata(format!("the metadata claims to be {} bytes long", length))));
+        }
+        if copy.len() < blocks * METADATA_BLOCK_SIZE {
+            return None;
+        }
+        let ecc_decoder = EccDecoder::new(METADATA_ECC_SIZE);
+        let mut stream = first.to_vec();
+        for block in copy[METADATA_BLOCK_SIZE..(blocks * METADATA_BLOCK_SIZE)].chunks(METADATA_BLOCK_SIZE) {
+            stream.extend_from_slice(ecc_decoder.correct(block, None).ok()?.data());
+        }
+        Some(Metadata::from_fields(&stream[4..(4 + length)]))
+    }
+}
+
+/// every encoding version we can decode, the newest first
+pub fn versions() -> Vec<u16> {
+    let mut versions = vec![ENCODING_VERSION];
+    versions.extend_from_slice(&legacy::LEGACY_VERSIONS);
+    versions
+}
+
+/// turns seconds since the unix epoch into a date and time people can read
+pub fn format_timestamp(seconds: u64) -> String {
+    // the days since 0000-03-01 in the proleptic Gregorian calendar, years start in March so leap days come last
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = seconds % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// metadata with every field that isn't about encryption filled in
+    fn sample() -> Metadata {
+        Metadata {
+            file_name: String::from("backup.tar"), file_size: 123456789, crc32: 0xdeadbeef, ecc_bytes: 32, palette: PaletteFamily::Color,
+            colors: 16, pixel_size: 2, width: 1920, height: 1080, archive_id: Some([7; 16]), interleaved: true, calibration: true,
+            fiducials: true, trailing_copy: true, parity_group: 10, parity_frames: 2, created: Some(1700000000), modified: Some(981173106),
+            comment: Some(String::from("holiday photos ☀")), hash: Some((HashAlgorithm::Blake3, vec![0xab; 32])),
+            compression: Some((Compression::Zstd, 987654321)), encryption: None, directory: true,
+            part: Some(Part { index: 3, first_frame: 4000, data_frames: 1000 }),
+        }
+    }
+
+    /// metadata with only the required fields
+    fn minimal() -> Metadata {
+        Metadata { file_name: String::from("a"), file_size: 1, crc32: 1, ecc_bytes: 0, colors: 2, pixel_size: 1, width: 8, height: 8,
+                   ..Metadata::empty() }
+    }
+
+    /// error corrects (bytes) again after they got changed
+    fn reencode(bytes: &mut [u8]) {
+        let ecc_encoder = EccEncoder::new(METADATA_ECC_SIZE);
+        for block in bytes.chunks_mut(METADATA_BLOCK_SIZE) {
+            let ecc = ecc_encoder.encode(&block[..METADATA_BLOCK_DATA]).ecc().to_vec();
+            block[METADATA_BLOCK_DATA..].copy_from_slice(&ecc);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for metadata in [sample(), minimal()] {
+            let bytes = metadata.to_bytes().unwrap();
+            assert_eq!(bytes.len() % METADATA_BLOCK_SIZE, 0);
+            assert_eq!(Metadata::read(&bytes, ENCODING_VERSION).unwrap().unwrap(), metadata);
+        }
+    }
+
+    #[test]
+    fn round_trip_over_several_blocks() {
+        let mut metadata = sample();
+        metadata.comment = Some("a".repeat(1000));
+        let bytes = metadata.to_bytes().unwrap();
+        assert!(bytes.len() > 4 * METADATA_BLOCK_SIZE);
+        assert_eq!(Metadata::read(&bytes, ENCODING_VERSION).unwrap().unwrap(), metadata);
+        // the copy is cut off, the decoder has to look for a longer one
+        assert!(Metadata::read(&bytes[..bytes.len() - METADATA_BLOCK_SIZE], ENCODING_VERSION).is_none());
+
+        metadata.comment = Some("a".repeat(MAX_METADATA_BLOCKS * METADATA_BLOCK_DATA));
+        assert!(matches!(metadata.to_bytes(), Err(VideobackupError::InvalidParameters(_))));
+    }
+
+    #[test]
+    fn damaged_copy_is_corrected() {
+        let metadata = sample();
+        let mut bytes = metadata.to_bytes().unwrap();
+        for block in bytes.chunks_mut(METADATA_BLOCK_SIZE) {
+            // as many errors as the ECC can correct
+            for byte in block.iter_mut().step_by(METADATA_BLOCK_SIZE.div_ceil(METADATA_ECC_SIZE / 2)) {
+                *byte ^= 0x55;
+            }
+        }
+        assert_eq!(Metadata::read(&bytes, ENCODING_VERSION).unwrap().unwrap(), metadata);
+    }
+
+    #[test]
+    fn versions_are_told_apart() {
+        let mut bytes = sample().to_bytes().unwrap();
+        assert!(Metadata::read(&bytes, 3).is_none());
+        assert!(Metadata::read(&vec![0; METADATA_BLOCK_SIZE], ENCODING_VERSION).is_none());
+        bytes[0..=1].copy_from_slice(&(ENCODING_VERSION + 1).to_be_bytes());
+        reencode(&mut bytes);
+        assert!(matches!(Metadata::read(&bytes, ENCODING_VERSION), Some(Err(VideobackupError::UnsupportedVersion { .. }))));
+    }
+
+    /// a metadata block the way the baseline encoder wrote it, with fixed fields and the ECC over bytes 0-217
+    fn baseline_block(file_name: &str, colors: u16, file_size: u64, crc32: u32, ecc_bytes: u8) -> Vec<u8> {
+        let mut block = vec![0; 218];
+        block[0..=1].copy_from_slice(&3u16.to_be_bytes());
+        block[2..=3].copy_from_slice(&colors.to_be_bytes());
+        block[4] = 1;
+        block[5..=12].copy_from_slice(&file_size.to_be_bytes());
+        block[13..=16].copy_from_slice(&crc32.to_be_bytes());
+        block[17] = ecc_bytes;
+        block[18..(18 + file_name.len())].copy_from_slice(file_name.as_bytes());
+        EccEncoder::new(32).encode(&block).to_vec()
+    }
+
+    #[test]
+    fn version_3_block() {
+        let mut block = baseline_block("backup.tar", 8, 123456789, 0xdeadbeef, 16);
+        assert_eq!(block.len(), 250);
+        let metadata = Metadata::read(&block, 3).unwrap().unwrap();
+        assert_eq!(metadata.file_name, "backup.tar");
+        assert_eq!(metadata.colors, 8);
+        assert_eq!(metadata.pixel_size, 1);
+        assert_eq!(metadata.file_size, 123456789);
+        assert_eq!(metadata.crc32, 0xdeadbeef);
+        assert_eq!(metadata.ecc_bytes, 16);
+        assert_eq!(metadata.palette, PaletteFamily::Color);
+        assert!(metadata.encryption.is_none() && metadata.part.is_none());
+
+        // the palette size sits where version 4 has the length of its fields, so the block must never be read as one
+        assert!(Metadata::read(&block, ENCODING_VERSION).is_none());
+        block.extend_from_slice(&vec![0; MAX_METADATA_BLOCKS * METADATA_BLOCK_SIZE]);
+        assert!(Metadata::read(&block, ENCODING_VERSION).is_none());
+        assert_eq!(Metadata::read(&block, 3).unwrap().unwrap(), metadata);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let metadata = sample();
+        let mut fields = metadata.fields(false);
+        push_field(&mut fields, 0x7f, b"from the future");
+        assert_eq!(Metadata::from_fields(&fields).unwrap(), metadata);
+        push_field(&mut fields, CRITICAL | 0x7f, b"from the future");
+        assert!(matches!(Metadata::from_fields(&fields), Err(VideobackupError::UnsupportedFeature(_))));
+    }
+
+    #[test]
+    fn broken_fields_are_rejected() {
+        let fields = minimal().fields(false);
+        // the last field is cut off
+        assert!(matches!(Metadata::from_fields(&fields[..fields.len() - 1]), Err(VideobackupError::CorruptMetadata(_))));
+
+        let mut missing = Vec::new();
+        push_field(&mut missing, TAG_FILE_NAME, b"a");
+        assert!(matches!(Metadata::from_fields(&missing), Err(VideobackupError::CorruptMetadata(_))));
+
+        let mut wrong_length = fields.clone();
+        push_field(&mut wrong_length, TAG_CREATED, &[0; 4]);
+        assert!(matches!(Metadata::from_fields(&wrong_length), Err(VideobackupError::CorruptMetadata(_))));
+
+        let mut outside = minimal();
+        outside.file_name = String::from("../a");
+        assert!(matches!(Metadata::from_fields(&outside.fields(false)), Err(VideobackupError::CorruptMetadata(_))));
+    }
+
+    #[test]
+    fn sealed_round_trip() {
+        let key = [1; 32];
+        let mut metadata = sample();
+        metadata.encryption = Some(Encryption {
+            chunk_size: 1000,
+            recipients: vec![Recipient::Passphrase { salt: [2; 16], kdf: KdfParams { memory: 8, iterations: 1, parallelism: 1 }, wrapped_key: vec![3; 48] },
+                             Recipient::X25519 { share: [4; 32], wrapped_key: vec![5; 48] }],
+            sealed: Vec::new(),
+        });
+        metadata.seal(&key);
+        let bytes = metadata.to_bytes().unwrap();
+        // nothing about the file is out in the open
+        for secret in [&b"backup.tar"[..], b"holiday photos", &[0xab; 32]] {
+            assert!(!bytes.windows(secret.len()).any(|w| w == secret));
+        }
+
+        let mut read = Metadata::read(&bytes, ENCODING_VERSION).unwrap().unwrap();
+        assert_eq!(read.file_name, "");
+        assert_eq!(read.comment, None);
+        assert!(matches!(read.clone().unseal(&[9; 32]), Err(VideobackupError::CorruptMetadata(_))));
+        read.unseal(&key).unwrap();
+        assert_eq!(read, metadata);
+    }
+
+    #[test]
+    fn sealed_part_is_bound_to_the_file_size() {
+        let key = [1; 32];
+        let mut metadata = minimal();
+        metadata.encryption = Some(Encryption { chunk_size: 1000, recipients: Vec::new(), sealed: Vec::new() });
+        metadata.seal(&key);
+        let mut read = Metadata::read(&metadata.to_bytes().unwrap(), ENCODING_VERSION).unwrap().unwrap();
+        read.file_size += 1;
+        assert!(matches!(read.unseal(&key), Err(VideobackupError::CorruptMetadata(_))));
+    }
+}
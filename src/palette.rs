@@ -0,0 +1,228 @@
+use crate::error::{Result, VideobackupError};
+
+use std::fmt;
+use std::str::FromStr;
+
+
+/// the most colors a palette can have, a cell never holds more than a byte
+pub const MAX_COLORS: u16 = 256;
+
+/// the most shades of gray a grayscale palette can have, any more and compression blurs them into each other
+pub const MAX_GRAY_LEVELS: u16 = 8;
+
+/// The kinds of palettes a video can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteFamily {
+    /// colors spread over all three color channels
+    Color,
+    /// shades of gray only, they're carried by the luma channel alone and don't care about chroma subsampling
+    Grayscale,
+}
+
+impl PaletteFamily {
+    /// the byte the family is stored as in the metadata
+    pub fn to_byte(self) -> u8 {
+        match self {
+            PaletteFamily::Color => 0,
+            PaletteFamily::Grayscale => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(PaletteFamily::Color),
+            1 => Some(PaletteFamily::Grayscale),
+            _ => None,
+        }
+    }
+
+    /// whether there's a palette of this family with (colors) colors
+    pub fn supports(self, colors: u16) -> bool {
+        (2..=self.max_colors()).contains(&colors) && colors.is_power_of_two()
+    }
+
+    pub fn max_colors(self) -> u16 {
+        match self {
+            PaletteFamily::Color => MAX_COLORS,
+            PaletteFamily::Grayscale => MAX_GRAY_LEVELS,
+        }
+    }
+}
+
+impl fmt::Display for PaletteFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PaletteFamily::Color => "color",
+            PaletteFamily::Grayscale => "grayscale",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PaletteFamily {
+    type Err = VideobackupError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "color" | "colour" => Ok(PaletteFamily::Color),
+            "grayscale" | "greyscale" | "gray" | "grey" => Ok(PaletteFamily::Grayscale),
+            _ => Err(VideobackupError::InvalidParameters(format!("{} is not a palette, use color or grayscale", s))),
+        }
+    }
+}
+
+/// The colors every cell of a data frame can have, each color stands for a symbol of `bits()` bits.
+///
+/// 2 and 4 colors keep the palettes videobackup always had (black and white; black, red, green and blue). Larger palettes
+/// spread the bits of a symbol over the color channels (green gets the most since codecs keep the most detail there),
+/// each channel uses evenly spaced levels and the levels are Gray-coded, so mistaking a color for one of its nearest
+/// neighbours only flips a single bit. Grayscale palettes are evenly spaced, Gray-coded shades of gray.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    bits: u32,
+}
+
+fn gray_to_binary(mut gray: usize) -> usize {
+    let mut binary = gray;
+    while gray > 1 {
+        gray >>= 1;
+        binary ^= gray;
+    }
+    binary
+}
+
+impl Palette {
+    /// the palette of (family) with (colors) colors, check `PaletteFamily::supports` first
+    pub fn new(colors: u16, family: PaletteFamily) -> Self {
+        let bits = colors.trailing_zeros();
+        let level = |gray: usize, channel_bits: u32| -> u8 {
+            (gray_to_binary(gray) * 255 / ((1 << channel_bits) - 1)) as u8
+        };
+        let colors = match (colors, family) {
+            (_, PaletteFamily::Grayscale) => (0..colors as usize).map(|symbol| [level(symbol, bits); 3]).collect(),
+            (2, _) => vec![[0, 0, 0], [255, 255, 255]],
+            (4, _) => vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]],
+            _ => {
+                // symbols look like this: green bits, red bits, blue bits
+                let green_bits = bits.div_ceil(3);
+                let red_bits = (bits + 1) / 3;
+                let blue_bits = bits / 3;
+                (0..colors as usize).map(|symbol| {
+                    let blue = symbol & ((1 << blue_bits) - 1);
+                    let red = (symbol >> blue_bits) & ((1 << red_bits) - 1);
+                    let green = symbol >> (blue_bits + red_bits);
+                    [level(red, red_bits), level(green, green_bits), level(blue, blue_bits)]
+                }).collect()
+            }
+        };
+        Palette { colors, bits }
+    }
+
+    /// how many bits every color stands for
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// the color of (symbol)
+    pub fn color(&self, symbol: usize) -> [u8; 3] {
+        self.colors[symbol]
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// checks that all colors of (palette) differ and mistaking a color for its nearest neighbour in a channel only flips
+    /// a single bit
+    fn check_gray_coded(palette: &Palette) {
+        let colors = palette.colors();
+        for a in 0..colors.len() {
+            for b in (a + 1)..colors.len() {
+                assert_ne!(colors[a], colors[b], "symbols {} and {}", a, b);
+                let differing: Vec<usize> = (0..3).filter(|c| colors[a][*c] != colors[b][*c]).collect();
+                if differing.len() != 1 {
+                    continue;
+                }
+                let channel = differing[0];
+                let (low, high) = (colors[a][channel].min(colors[b][channel]), colors[a][channel].max(colors[b][channel]));
+                let between = colors.iter().any(|c| c[channel] > low && c[channel] < high);
+                if !between {
+                    assert_eq!((a ^ b).count_ones(), 1, "symbols {} and {} are neighbours", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn baseline_palettes() {
+        assert_eq!(Palette::new(2, PaletteFamily::Color).colors(), [[0, 0, 0], [255, 255, 255]]);
+        assert_eq!(Palette::new(4, PaletteFamily::Color).colors(), [[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+    }
+
+    #[test]
+    fn color_palettes_are_gray_coded() {
+        for bits in 3..=8 {
+            let palette = Palette::new(1 << bits, PaletteFamily::Color);
+            assert_eq!(palette.bits(), bits);
+            assert_eq!(palette.colors().len(), 1 << bits);
+            check_gray_coded(&palette);
+            // every channel uses the whole range
+            for channel in 0..3 {
+                assert_eq!(palette.colors().iter().map(|c| c[channel]).min(), Some(0));
+                assert_eq!(palette.colors().iter().map(|c| c[channel]).max(), Some(255));
+            }
+        }
+    }
+
+    #[test]
+    fn gray_code() {
+        for gray in 0..256usize {
+            let binary = gray_to_binary(gray);
+            assert_eq!(binary ^ (binary >> 1), gray);
+        }
+    }
+
+    #[test]
+    fn supported_colors() {
+        for colors in [2, 4, 8, 16, 32, 64, 128, 256] {
+            assert!(PaletteFamily::Color.supports(colors));
+        }
+        for colors in [0, 1, 3, 6, 255, 512] {
+            assert!(!PaletteFamily::Color.supports(colors));
+        }
+    }
+
+    #[test]
+    fn grayscale_palettes() {
+        for bits in 1..=3 {
+            let palette = Palette::new(1 << bits, PaletteFamily::Grayscale);
+            assert_eq!(palette.bits(), bits);
+            check_gray_coded(&palette);
+            // evenly spaced shades from black to white
+            let mut shades: Vec<u8> = palette.colors().iter().map(|c| {
+                assert!(c[0] == c[1] && c[1] == c[2], "{:?} isn't gray", c);
+                c[0]
+            }).collect();
+            shades.sort_unstable();
+            assert!(shades.iter().enumerate().all(|(i, s)| *s as usize == i * 255 / ((1 << bits) - 1)), "{:?}", shades);
+        }
+        assert!(PaletteFamily::Grayscale.supports(MAX_GRAY_LEVELS));
+        assert!(!PaletteFamily::Grayscale.supports(MAX_GRAY_LEVELS * 2));
+    }
+
+    #[test]
+    fn families() {
+        for family in [PaletteFamily::Color, PaletteFamily::Grayscale] {
+            assert_eq!(PaletteFamily::from_byte(family.to_byte()), Some(family));
+            assert_eq!(family.to_string().parse::<PaletteFamily>().unwrap(), family);
+        }
+        assert_eq!(PaletteFamily::from_byte(2), None);
+        assert!("rainbow".parse::<PaletteFamily>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+extern crate reed_solomon_erasure;
+
+use crate::error::{Result, VideobackupError};
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+
+/// the erasure code can't handle more than this many frames per group, data and parity frames combined
+pub const MAX_GROUP_FRAMES: usize = 256;
+
+fn codec(data_frames: usize, parity_frames: usize) -> Result<ReedSolomon> {
+    match ReedSolomon::new(data_frames, parity_frames) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(VideobackupError::InvalidParameters(format!("Can't protect {} data frames with {} parity frames: {:?}", data_frames, parity_frames, e))),
+    }
+}
+
+/// calculates (parity_frames) parity frames for a group of data frames, a group with less than (group_size) frames
+/// (the last one in a file) gets padded with frames full of zeros
+pub fn parity_frames(group: &[Vec<u8>], group_size: usize, parity_frames: usize, frame_size: usize) -> Result<Vec<Vec<u8>>> {
+    let mut shards: Vec<Vec<u8>> = Vec::with_capacity(group_size + parity_frames);
+    for i in 0..(group_size + parity_frames) {
+        match group.get(i) {
+            Some(v) if i < group_size => shards.push(v.clone()),
+            _ => shards.push(vec![0; frame_size]),
+        }
+    }
+    if let Err(e) = codec(group_size, parity_frames)?.encode(&mut shards) {
+        return Err(VideobackupError::InvalidParameters(format!("Unable to calculate parity frames: {:?}", e)));
+    }
+    Ok(shards.split_off(group_size))
+}
+
+/// fills in the missing (None) data frames of a group from the frames that are still there, (frames) has to contain
+/// the group's data frames followed by its parity frames, returns false if too many frames are missing
+pub fn reconstruct(frames: &mut [Option<Vec<u8>>], group_size: usize, parity_frames: usize) -> bool {
+    match codec(group_size, parity_frames) {
+        Ok(v) => v.reconstruct_data(frames).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a group of (count) frames that all look different
+    fn group(count: usize, frame_size: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|f| (0..frame_size).map(|i| (i * 31 + f * 7) as u8).collect()).collect()
+    }
+
+    #[test]
+    fn missing_frames_are_reconstructed() {
+        let data = group(5, 100);
+        let parity = parity_frames(&data, 5, 2, 100).unwrap();
+        assert_eq!(parity.len(), 2);
+        for missing in [vec![0], vec![4], vec![1, 3], vec![2, 5], vec![5, 6]] {
+            let mut frames: Vec<Option<Vec<u8>>> = data.iter().chain(parity.iter()).cloned().map(Some).collect();
+            for m in &missing {
+                frames[*m] = None;
+            }
+            assert!(reconstruct(&mut frames, 5, 2), "{:?}", missing);
+            let reconstructed: Vec<Vec<u8>> = frames.into_iter().take(5).map(Option::unwrap).collect();
+            assert_eq!(reconstructed, data, "{:?}", missing);
+        }
+    }
+
+    #[test]
+    fn too_many_missing_frames() {
+        let data = group(5, 100);
+        let parity = parity_frames(&data, 5, 2, 100).unwrap();
+        let mut frames: Vec<Option<Vec<u8>>> = data.iter().chain(parity.iter()).cloned().map(Some).collect();
+        for frame in frames.iter_mut().take(3) {
+            *frame = None;
+        }
+        assert!(!reconstruct(&mut frames, 5, 2));
+    }
+
+    #[test]
+    fn short_group_is_padded() {
+        // the last group of a file has less frames, the missing ones count as zeros
+        let data = group(3, 100);
+        let parity = parity_frames(&data, 5, 2, 100).unwrap();
+        let mut frames: Vec<Option<Vec<u8>>> = data.iter().cloned().map(Some).collect();
+        frames.extend([Some(vec![0; 100]), Some(vec![0; 100])]);
+        frames.extend(parity.into_iter().map(Some));
+        frames[1] = None;
+        assert!(reconstruct(&mut frames, 5, 2));
+        assert_eq!(frames[1].as_ref(), Some(&data[1]));
+    }
+
+    #[test]
+    fn group_too_large() {
+        assert!(parity_frames(&group(1, 10), MAX_GROUP_FRAMES, 1, 10).is_err());
+    }
+}